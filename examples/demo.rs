@@ -46,7 +46,7 @@ fn run() -> Result<()> {
     // Create the database and storage file. If `demo.json` does not exist,
     // it will be created with default values
     let file = Path::new("demo.json");
-    let db: Mvdb<NotADb> = Mvdb::from_file_or_default(&file)?;
+    let db: Mvdb<NotADb> = Mvdb::from_file_or_default(file)?;
 
     // Access the database contents atomically via a closure. You may
     // optionally return a value (of any type) from the closure, which will
@@ -63,6 +63,14 @@ fn run() -> Result<()> {
     })?;
     println!("y: {:?}", y);
 
+    // Large parts of the database can be read without cloning them, by
+    // holding a guard instead. As with the closures above, no other access
+    // can be made until the guard is dropped
+    {
+        let multiple = db.map_read(|data| &data.multiple)?;
+        println!("multiple: {}", multiple.len());
+    }
+
     // Access the database contents atomically via a closure. You may
     // optionally return a value (of any type) from the closure, which will
     // be wrapped in a Result. Changes will be written if the database contents
@@ -0,0 +1,294 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use errors::*;
+use helpers::{serialize, unix_millis};
+use mvdb::{Change, Mvdb};
+use patch::diff;
+use storage::{FileStorage, Storage};
+
+/// The `prev_hash` of the first entry in an audit log
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An append-only, hash-chained record of every modification, stored as
+/// one JSON document per line
+pub(crate) struct AuditLog {
+    storage: Box<dyn Storage>,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Open an audit log, verifying any entries it already contains
+    fn open(storage: Box<dyn Storage>) -> Result<Self> {
+        let last_hash = verify_entries(&*storage)?.1;
+        Ok(Self { storage, last_hash })
+    }
+
+    /// Append an entry describing a modification from `previous` to `contents`
+    pub(crate) fn record(
+        &mut self,
+        generation: u64,
+        actor: Option<&str>,
+        reason: Option<&str>,
+        previous: &str,
+        contents: &str,
+    ) -> Result<()> {
+        let before: Value = serde_json::from_str(previous)
+            .chain_err(|| "Deserialize error")?;
+        let after: Value = serde_json::from_str(contents)
+            .chain_err(|| "Deserialize error")?;
+
+        let mut entry = json!({
+            "generation": generation,
+            "timestamp_ms": unix_millis(SystemTime::now()),
+            "actor": actor,
+            "reason": reason,
+            "patch": diff(&before, &after),
+            "prev_hash": self.last_hash,
+        });
+        let hash = entry_hash(&entry)?;
+        entry["hash"] = Value::String(hash.clone());
+
+        self.storage.append(format!("{}\n", serialize(&entry, false)?).as_bytes())?;
+        self.last_hash = hash;
+        Ok(())
+    }
+}
+
+/// Compute the hash of an audit entry, excluding its own `hash` field
+fn entry_hash(entry: &Value) -> Result<String> {
+    let mut entry = entry.clone();
+    if let Value::Object(ref mut map) = entry {
+        map.remove("hash");
+    }
+
+    let digest = Sha256::digest(serialize(&entry, false)?.as_bytes());
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Check every entry of an audit log, returning the number of entries
+/// and the hash of the last entry
+fn verify_entries(storage: &dyn Storage) -> Result<(usize, String)> {
+    let mut last_hash = GENESIS_HASH.to_string();
+    if !storage.exists()? {
+        return Ok((0, last_hash));
+    }
+
+    let contents = storage.read_all()?;
+    let contents = String::from_utf8_lossy(&contents);
+    let lines = contents.lines().filter(|line| !line.trim().is_empty());
+
+    let mut count = 0;
+    for (index, line) in lines.enumerate() {
+        let entry: Value = serde_json::from_str(line)
+            .chain_err(|| ErrorKind::AuditChainBroken(index))?;
+
+        let valid = entry["prev_hash"] == Value::String(last_hash.clone())
+            && entry["hash"] == Value::String(entry_hash(&entry)?);
+        if !valid {
+            bail!(ErrorKind::AuditChainBroken(index));
+        }
+
+        last_hash = entry_hash(&entry)?;
+        count += 1;
+    }
+    Ok((count, last_hash))
+}
+
+/// Check that no entry of the audit log at the given path has been modified,
+/// reordered, or removed from the middle of the log. Returns the number of
+/// entries in the log
+pub fn verify_audit_log(path: &Path) -> Result<usize> {
+    verify_audit_log_with_storage(&FileStorage::new(path))
+}
+
+/// Check that no entry of the audit log in the given `Storage` has been
+/// modified, reordered, or removed from the middle of the log. Returns the
+/// number of entries in the log
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use] extern crate serde_derive;
+/// # extern crate mvdb;
+/// # use mvdb::{Mvdb, verify_audit_log_with_storage};
+/// # use mvdb::errors::*;
+/// # use mvdb::storage::{MemoryStorage, Storage};
+/// # #[derive(Serialize, Deserialize, Default)]
+/// # struct Config { token: String }
+/// # fn main() {
+/// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+/// let audit = MemoryStorage::new();
+/// my_data.enable_audit_with_storage(audit.clone()).unwrap();
+/// for token in &["abc", "def", "ghi"] {
+///     my_data.access_mut(|cfg| cfg.token = token.to_string()).unwrap();
+/// }
+///
+/// let log = String::from_utf8(audit.contents().unwrap()).unwrap();
+/// let entries: Vec<&str> = log.lines().collect();
+/// let broken_at = |lines: &[&str]| {
+///     let tampered = MemoryStorage::with_contents(format!("{}\n", lines.join("\n")).as_bytes());
+///     match verify_audit_log_with_storage(&tampered) {
+///         Err(Error(ErrorKind::AuditChainBroken(index), _)) => Some(index),
+///         _ => None,
+///     }
+/// };
+///
+/// // Modified, reordered and removed entries are detected
+/// let modified = entries[1].replace("def", "xyz");
+/// assert_eq!(broken_at(&[entries[0], &modified, entries[2]]), Some(1));
+/// assert_eq!(broken_at(&[entries[0], entries[2], entries[1]]), Some(1));
+/// assert_eq!(broken_at(&[entries[1], entries[2]]), Some(0));
+/// assert_eq!(broken_at(&[entries[0], entries[2]]), Some(1));
+/// assert_eq!(broken_at(&[entries[0], "not json", entries[2]]), Some(1));
+/// assert_eq!(broken_at(&entries), None);
+///
+/// // A tampered log cannot be appended to
+/// audit.write_all(format!("{}\n{}\n", entries[0], entries[2]).as_bytes()).unwrap();
+/// match my_data.enable_audit_with_storage(audit.clone()) {
+///     Err(Error(ErrorKind::AuditChainBroken(1), _)) => (),
+///     _ => panic!("Tampering was not detected"),
+/// }
+/// # }
+/// ```
+pub fn verify_audit_log_with_storage(storage: &dyn Storage) -> Result<usize> {
+    Ok(verify_entries(storage)?.0)
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Start recording every modification in an append-only audit log at the
+    /// given path. Each entry holds the actor, reason, timestamp and generation
+    /// of the modification, along with a JSON Patch of the changes. Entries are
+    /// chained together by hash, so that tampering can be detected with
+    /// `verify_audit_log`. If the existing log has been tampered with, an
+    /// `ErrorKind::AuditChainBroken` is returned.
+    ///
+    /// No modification is persisted without being audited. If an entry cannot
+    /// be appended, the previous contents are restored, both in the file and
+    /// in memory, and the Error is returned
+    pub fn enable_audit(&self, path: &Path) -> Result<()> {
+        self.enable_audit_with_storage(FileStorage::new(path))
+    }
+
+    /// Start recording every modification in an append-only audit log in the
+    /// given `Storage`. See `enable_audit` for details
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{Mvdb, verify_audit_log_with_storage};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { token: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// let audit = MemoryStorage::new();
+    /// my_data.enable_audit_with_storage(audit.clone())
+    ///     .expect("Audit log has been tampered with");
+    ///
+    /// my_data.access_mut_as("alice", "rotate token", |cfg| cfg.token = "abc123".into())
+    ///     .expect("Failed to access file");
+    ///
+    /// // Handles may also carry a default actor
+    /// let scheduler = my_data.with_actor("scheduler");
+    /// scheduler.access_mut(|cfg| cfg.token = "def456".into())
+    ///     .expect("Failed to access file");
+    ///
+    /// assert_eq!(verify_audit_log_with_storage(&audit).unwrap(), 2);
+    /// # }
+    /// ```
+    ///
+    /// Modifications that cannot be audited are rolled back:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::errors::*;
+    /// # use mvdb::storage::{MemoryStorage, Storage, StorageMetadata};
+    /// # struct Full;
+    /// # impl Storage for Full {
+    /// #     fn read_all(&self) -> Result<Vec<u8>> { Ok(Vec::new()) }
+    /// #     fn write_all(&self, _: &[u8]) -> Result<()> { Err("No space left".into()) }
+    /// #     fn exists(&self) -> Result<bool> { Ok(false) }
+    /// #     fn metadata(&self) -> Result<StorageMetadata> { Err("No contents".into()) }
+    /// # }
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { token: String }
+    /// # fn main() {
+    /// # let storage = MemoryStorage::new();
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), storage.clone()).unwrap();
+    /// // An audit log that cannot be written to
+    /// my_data.enable_audit_with_storage(Full).expect("Audit log has been tampered with");
+    ///
+    /// assert!(my_data.access_mut(|cfg| cfg.token = "abc123".into()).is_err());
+    /// assert_eq!(my_data.access(|cfg| cfg.token.clone()).unwrap(), "");
+    /// assert_eq!(storage.contents().unwrap(), br#"{"token":""}"#.to_vec());
+    /// # }
+    /// ```
+    pub fn enable_audit_with_storage<S>(&self, storage: S) -> Result<()>
+    where
+        S: Storage + 'static,
+    {
+        let log = AuditLog::open(Box::new(storage))?;
+        self.lock()?.audit = Some(log);
+        Ok(())
+    }
+
+    /// Obtain a handle to the same contents whose modifications are recorded
+    /// in the audit log as made by the given actor, unless another actor is
+    /// given with `access_mut_as`
+    pub fn with_actor(&self, actor: &str) -> Self {
+        let mut handle = self.clone();
+        handle.actor = Some(Arc::from(actor));
+        handle
+    }
+
+    /// Provide atomic writable access to the database contents via a closure,
+    /// as with `access_mut`. If the contents are modified, the audit log entry
+    /// for the modification will record the given actor and reason
+    pub fn access_mut_as<F, R>(&self, actor: &str, reason: &str, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.access_mut_inner(Change {
+            actor: Some(actor),
+            reason: Some(reason),
+            ..Change::default()
+        }, action)
+    }
+}
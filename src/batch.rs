@@ -0,0 +1,168 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use layers::Layers;
+use mvdb::{Change, Mvdb};
+
+/// A series of accesses made while holding the lock of an `Mvdb`, which
+/// are persisted together, at most once, when the batch completes
+pub struct Batch<'a, T: 'a> {
+    data: &'a mut T,
+    layers: &'a Layers,
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Make several accesses to the database contents while holding the lock
+    /// throughout. Changes are only checked for and written to the file once,
+    /// after the closure returns `Ok`. If the closure returns an Error, all
+    /// changes made during the batch are rolled back, and nothing is written.
+    /// A batch that returns an Error without changing the serialized contents
+    /// is not rolled back at all.
+    ///
+    /// Changes are rolled back by deserializing the contents as they were
+    /// before the batch, which resets any fields that are not serialized,
+    /// such as those marked `#[serde(skip)]`, to their default values. Call
+    /// `rollback_by_clone` to keep them instead
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let writes = my_data.stats().writes;
+    ///
+    /// my_data.batch(|tx| {
+    ///     tx.access_mut(|db| db.foo = "New Value".into())?;
+    ///     let len = tx.access(|db| db.foo.len())?;
+    ///     tx.access_mut(|db| db.bar.push(len as u8))?;
+    ///     Ok(())
+    /// }).expect("Failed to access file");
+    ///
+    /// // The file was written only once
+    /// assert_eq!(my_data.stats().writes, writes + 1);
+    /// # }
+    /// ```
+    ///
+    /// A batch that only reads the contents never resets them:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct DemoData {
+    ///     foo: String,
+    ///     #[serde(skip)]
+    ///     scratch: Vec<u8>,
+    /// }
+    ///
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// my_data.access_mut(|db| db.scratch.push(7)).expect("Failed to access file");
+    ///
+    /// let checked: Result<(), _> = my_data.batch(|tx| {
+    ///     tx.access(|db| db.foo.is_empty())?;
+    ///     Err("Nothing to do".into())
+    /// });
+    ///
+    /// assert!(checked.is_err());
+    /// assert_eq!(my_data.access(|db| db.scratch.clone()).unwrap(), vec![7]);
+    /// # }
+    /// ```
+    pub fn batch<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut Batch<T>) -> Result<R>,
+    {
+        trace_span!("mvdb::batch");
+        let mut x = self.lock()?;
+        let before = self.begin_locked(&mut x)?;
+
+        let ret = {
+            let inner = &mut *x;
+            action(&mut Batch {
+                data: &mut inner.data,
+                layers: &inner.layers,
+            })
+        };
+        match ret {
+            Ok(ret) => {
+                self.commit_locked(&mut x, before, Change::default())?;
+                Ok(ret)
+            }
+            Err(e) => {
+                // Rolling back may reset fields that are not serialized, so
+                // only do so if the batch changed the contents. Once rolled
+                // back, overrides and indexes already match the contents
+                let rolled_back = self.hash_timed(&x.data).and_then(|(_, hash)| match hash == before.1 {
+                    true => Ok(()),
+                    false => Self::rollback_locked(&mut x, &before.0),
+                });
+                x.snapshot = None;
+                if rolled_back.is_err() {
+                    x.refresh();
+                }
+                rolled_back?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a, T> Batch<'a, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Provide read-only access to the database contents as they currently
+    /// stand within the batch, including any overrides
+    pub fn access<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        match self.layers.overlay(&*self.data)? {
+            Some(overlaid) => Ok(action(&overlaid)),
+            None => Ok(action(self.data)),
+        }
+    }
+
+    /// Provide writable access to the database contents. Changes are not
+    /// written until the batch completes
+    pub fn access_mut<F, R>(&mut self, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        Ok(action(self.data))
+    }
+}
@@ -0,0 +1,127 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A command line tool for inspecting and editing `mvdb` files
+//!
+//! Files are opened as untyped JSON documents, and all modifications are
+//! made through `Mvdb`, so the same atomic write rules apply as in the library.
+
+#[macro_use]
+extern crate error_chain;
+extern crate mvdb;
+extern crate serde_json;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use serde_json::Value;
+
+use mvdb::Mvdb;
+use mvdb::errors::*;
+use mvdb::helpers::serialize;
+use mvdb::storage::{FileStorage, Storage};
+
+const USAGE: &str = "\
+Usage: mvdb <command> [args]
+
+Commands:
+    show <file>                        Print the contents of a file, pretty-printed
+    get <file> <pointer>               Print the value at a JSON pointer, e.g. `/network/port`
+    set <file> <pointer> <json>        Set the value at a JSON pointer
+    validate <file>                    Check that a file contains a valid document
+    convert <file> <output> [--pretty] Write the contents of a file to another file
+";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+        process::exit(1);
+    }
+}
+
+fn run(args: &[&str]) -> Result<()> {
+    match *args {
+        ["show", file] => show(Path::new(file)),
+        ["get", file, pointer] => get(Path::new(file), pointer),
+        ["set", file, pointer, value] => set(Path::new(file), pointer, value),
+        ["validate", file] => validate(Path::new(file)),
+        ["convert", file, output] => convert(Path::new(file), Path::new(output), false),
+        ["convert", file, output, "--pretty"] => convert(Path::new(file), Path::new(output), true),
+        ["help"] | ["--help"] | ["-h"] => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => bail!("Invalid arguments\n\n{}", USAGE),
+    }
+}
+
+/// Open a file as an untyped document. Writes will keep the file
+/// pretty-printed if it already was
+fn open(path: &Path) -> Result<Mvdb<Value>> {
+    let contents = FileStorage::new(path).read_all()?;
+    match String::from_utf8_lossy(&contents).trim_end().contains('\n') {
+        true => Mvdb::from_file_pretty(path),
+        false => Mvdb::from_file(path),
+    }
+}
+
+fn show(path: &Path) -> Result<()> {
+    let db = open(path)?;
+    let contents = db.access(|doc| serialize(doc, true))??;
+    println!("{}", contents);
+    Ok(())
+}
+
+fn get(path: &Path, pointer: &str) -> Result<()> {
+    let value = open(path)?.get_path(pointer)?;
+    println!("{}", serialize(&value, true)?);
+    Ok(())
+}
+
+fn set(path: &Path, pointer: &str, value: &str) -> Result<()> {
+    let value: Value = serde_json::from_str(value)
+        .chain_err(|| "Value is not valid JSON")?;
+    open(path)?.set_path(pointer, value)
+}
+
+fn validate(path: &Path) -> Result<()> {
+    open(path)?;
+    println!("{}: ok", path.display());
+    Ok(())
+}
+
+fn convert(path: &Path, output: &Path, pretty: bool) -> Result<()> {
+    let db = open(path)?;
+    let doc = db.access(|doc| doc.clone())?;
+    match pretty {
+        true => Mvdb::new_pretty(doc, output)?,
+        false => Mvdb::new(doc, output)?,
+    };
+    Ok(())
+}
@@ -0,0 +1,144 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::Mvdb;
+
+/// A value computed from the contents of an `Mvdb`
+///
+/// The value is computed when first requested, and cached until modified
+/// contents are written to storage, after which it is computed again the
+/// next time it is requested. Clones of a `Derived` share the same cache.
+pub struct Derived<R> {
+    derivation: Arc<dyn Derivation<R>>,
+}
+
+/// Implement `Clone` manually, otherwise Rust expects `R` to also impl `Clone`,
+/// which is not necessary
+impl<R> Clone for Derived<R> {
+    fn clone(&self) -> Self {
+        Self {
+            derivation: self.derivation.clone(),
+        }
+    }
+}
+
+/// Type-erased computation of a derived value, so that `Derived` does not
+/// need to name the type of the database
+trait Derivation<R>: Send + Sync {
+    fn get(&self) -> Result<Arc<R>>;
+}
+
+/// The generation of the contents, and the version of any overrides
+type Version = (u64, u64);
+
+/// A computation over an `Mvdb<T>`, and the last value it produced along
+/// with the generation and overrides it was computed from
+struct Cached<T, F, R> {
+    db: Mvdb<T>,
+    compute: F,
+    cache: Mutex<Option<(Version, Arc<R>)>>,
+}
+
+impl<T, F, R> Derivation<R> for Cached<T, F, R>
+where
+    T: Serialize + DeserializeOwned + Send,
+    F: Fn(&T) -> R + Send + Sync,
+    R: Send + Sync,
+{
+    fn get(&self) -> Result<Arc<R>> {
+        // Hold the database lock throughout, so the value is computed at
+        // most once per generation
+        let inner = self.db.lock()?;
+        let mut cache = match self.cache.lock() {
+            Ok(cache) => cache,
+            Err(_) => bail!("failed to lock"),
+        };
+
+        // Overrides may also change the contents seen by read-only accesses
+        let version = (inner.generation, inner.layers.version);
+        if let Some((cached, ref value)) = *cache {
+            if cached == version {
+                return Ok(value.clone());
+            }
+        }
+
+        let value = Arc::new((self.compute)(inner.visible()));
+        *cache = Some((version, value.clone()));
+        Ok(value)
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Create a value computed from the database contents by the given
+    /// closure, which is cached until the contents are next modified
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let total = my_data.derive(|db| db.bar.iter().map(|&b| u32::from(b)).sum::<u32>());
+    /// assert_eq!(*total.get().expect("Failed to access file"), 0);
+    ///
+    /// my_data.access_mut(|db| db.bar.extend(&[1, 2, 3]))
+    ///     .expect("Failed to access file");
+    ///
+    /// assert_eq!(*total.get().expect("Failed to access file"), 6);
+    /// # }
+    /// ```
+    pub fn derive<F, R>(&self, compute: F) -> Derived<R>
+    where
+        F: Fn(&T) -> R + Send + Sync + 'static,
+        R: Send + Sync + 'static,
+    {
+        Derived {
+            derivation: Arc::new(Cached {
+                db: self.clone(),
+                compute,
+                cache: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl<R> Derived<R> {
+    /// Obtain the derived value, computing it again only if the database
+    /// contents have been modified since it was last computed
+    pub fn get(&self) -> Result<Arc<R>> {
+        self.derivation.get()
+    }
+}
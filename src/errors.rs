@@ -22,4 +22,55 @@
 
 //! These types are generated by error_chain.
 
-error_chain!{}
\ No newline at end of file
+error_chain!{
+    errors {
+        /// A JSON pointer path did not refer to any value
+        PathNotFound(path: String) {
+            description("no value at path")
+            display("No value at path: {}", path)
+        }
+
+        /// A JSON Patch could not be applied to the document
+        PatchConflict(reason: String) {
+            description("patch could not be applied")
+            display("Patch could not be applied: {}", reason)
+        }
+
+        /// The contents were rejected by a validator
+        ValidationFailed(reason: String) {
+            description("contents failed validation")
+            display("Contents failed validation: {}", reason)
+        }
+
+        /// The requested version is not in the history
+        VersionNotFound(generation: u64) {
+            description("version not found in history")
+            display("Version {} not found in history", generation)
+        }
+
+        /// An entry of the audit log does not match the chain of hashes
+        AuditChainBroken(index: usize) {
+            description("audit log has been tampered with")
+            display("Audit log has been tampered with at entry {}", index)
+        }
+
+        /// The current thread already holds the lock, such as when accessing
+        /// the database from within another access
+        Reentrant {
+            description("lock is already held by the current thread")
+            display("Lock is already held by the current thread")
+        }
+
+        /// No index has been declared with the given name
+        IndexNotFound(name: String) {
+            description("index not found")
+            display("No index named {:?}", name)
+        }
+
+        /// The lock could not be acquired without waiting longer than allowed
+        WouldBlock {
+            description("lock could not be acquired in time")
+            display("Lock could not be acquired in time")
+        }
+    }
+}
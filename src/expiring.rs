@@ -0,0 +1,353 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{DeserializeOwned, Error as DeError, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+
+use helpers::unix_millis;
+use mvdb::Mvdb;
+
+/// A map of entries which each expire at a deadline
+///
+/// Expired entries are hidden from every method, but remain in the map (and
+/// in storage) until `remove_expired` is called, for example by a sweeper
+/// started with `Mvdb::start_sweeper`. Each entry is stored along with its
+/// deadline, in milliseconds since the Unix epoch, so deadlines survive a
+/// restart:
+///
+/// ```json
+/// { "token": { "value": "abc123", "expires_ms": 1700000000000 } }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expiring<K: Ord, V> {
+    entries: BTreeMap<K, Entry<V>>,
+}
+
+/// A value and its deadline, in milliseconds since the Unix epoch
+#[derive(Debug, Clone, PartialEq)]
+struct Entry<V> {
+    value: V,
+    expires_ms: u64,
+}
+
+impl<V> Entry<V> {
+    fn is_live(&self, now_ms: u64) -> bool {
+        self.expires_ms > now_ms
+    }
+}
+
+fn now_ms() -> u64 {
+    unix_millis(SystemTime::now())
+}
+
+impl<K: Ord, V> Default for Expiring<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V> Expiring<K, V> {
+    /// Create an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value which expires after the given duration, returning
+    /// the previous value for the key, if it had not expired. Durations
+    /// too long to represent are clamped to the latest possible deadline
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate mvdb;
+    /// # use mvdb::Expiring;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    /// let mut tokens = Expiring::new();
+    /// tokens.insert("session", "abc123", Duration::from_secs(60));
+    /// tokens.insert("forever", "def456", Duration::from_secs(u64::MAX));
+    ///
+    /// assert_eq!(tokens.get("session"), Some(&"abc123"));
+    /// assert_eq!(tokens.get("forever"), Some(&"def456"));
+    /// # }
+    /// ```
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let expires_ms = SystemTime::now()
+            .checked_add(ttl)
+            .map_or(u64::MAX, unix_millis);
+        self.insert_ms(key, value, expires_ms)
+    }
+
+    /// Insert a value which expires at the given time, returning the
+    /// previous value for the key, if it had not expired
+    pub fn insert_until(&mut self, key: K, value: V, deadline: SystemTime) -> Option<V> {
+        self.insert_ms(key, value, unix_millis(deadline))
+    }
+
+    /// Insert a value which expires at the given number of milliseconds
+    /// since the Unix epoch
+    fn insert_ms(&mut self, key: K, value: V, expires_ms: u64) -> Option<V> {
+        let entry = Entry { value, expires_ms };
+        let now = now_ms();
+        self.entries
+            .insert(key, entry)
+            .and_then(|old| if old.is_live(now) { Some(old.value) } else { None })
+    }
+
+    /// Get the value for a key, if it has not expired
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let now = now_ms();
+        self.entries
+            .get(key)
+            .and_then(|e| if e.is_live(now) { Some(&e.value) } else { None })
+    }
+
+    /// Get a mutable reference to the value for a key, if it has not expired
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let now = now_ms();
+        self.entries
+            .get_mut(key)
+            .and_then(|e| if e.is_live(now) { Some(&mut e.value) } else { None })
+    }
+
+    /// Whether the map holds a value for the key that has not expired
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// The time at which the value for a key expires, if it has not expired
+    pub fn expires_at<Q>(&self, key: &Q) -> Option<SystemTime>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let now = now_ms();
+        self.entries
+            .get(key)
+            .filter(|e| e.is_live(now))
+            .and_then(|e| UNIX_EPOCH.checked_add(Duration::from_millis(e.expires_ms)))
+    }
+
+    /// Remove the value for a key, returning it if it had not expired
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let now = now_ms();
+        self.entries
+            .remove(key)
+            .and_then(|e| if e.is_live(now) { Some(e.value) } else { None })
+    }
+
+    /// Iterate over the entries that have not expired, in key order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let now = now_ms();
+        self.entries
+            .iter()
+            .filter(move |&(_, e)| e.is_live(now))
+            .map(|(k, e)| (k, &e.value))
+    }
+
+    /// The number of entries that have not expired
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Whether every entry has expired, or the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Remove every expired entry, returning the number removed
+    pub fn remove_expired(&mut self) -> usize {
+        let now = now_ms();
+        let before = self.entries.len();
+        self.entries.retain(|_, e| e.is_live(now));
+        before - self.entries.len()
+    }
+}
+
+impl<K, V> Serialize for Expiring<K, V>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for Expiring<K, V>
+where
+    K: Ord + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            entries: BTreeMap::deserialize(deserializer)?,
+        })
+    }
+}
+
+impl<V: Serialize> Serialize for Entry<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("Entry", 2)?;
+        entry.serialize_field("value", &self.value)?;
+        entry.serialize_field("expires_ms", &self.expires_ms)?;
+        entry.end()
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for Entry<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntryVisitor<V>(PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for EntryVisitor<V> {
+            type Value = Entry<V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an entry with a value and expires_ms")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entry<V>, A::Error> {
+                let mut value = None;
+                let mut expires_ms = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "value" => value = Some(map.next_value()?),
+                        "expires_ms" => expires_ms = Some(map.next_value()?),
+                        _ => {
+                            map.next_value::<::serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(Entry {
+                    value: value.ok_or_else(|| A::Error::missing_field("value"))?,
+                    expires_ms: expires_ms.ok_or_else(|| A::Error::missing_field("expires_ms"))?,
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("Entry", &["value", "expires_ms"], EntryVisitor(PhantomData))
+    }
+}
+
+/// A background thread that periodically removes expired entries from an
+/// `Mvdb`, started by `Mvdb::start_sweeper`. The thread is stopped when the
+/// `Sweeper` is dropped
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the thread, which then exits
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Start a background thread which removes the expired entries of the
+    /// selected `Expiring` map at the given interval. Removals are persisted
+    /// like any other modification
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{Expiring, Mvdb};
+    /// # use mvdb::storage::MemoryStorage;
+    /// use std::time::Duration;
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Cache { sessions: Expiring<String, u32> }
+    ///
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Cache::default(), MemoryStorage::new()).unwrap();
+    /// let _sweeper = my_data.start_sweeper(Duration::from_secs(60), |c| &mut c.sessions);
+    ///
+    /// my_data.access_mut(|c| {
+    ///     c.sessions.insert("alice".into(), 1, Duration::from_secs(3600));
+    ///     c.sessions.insert("bob".into(), 2, Duration::from_secs(0));
+    /// }).expect("Failed to access file");
+    ///
+    /// let (alice, bob) = my_data.access(|c| {
+    ///     (c.sessions.get("alice").cloned(), c.sessions.get("bob").cloned())
+    /// }).expect("Failed to access file");
+    ///
+    /// assert_eq!(alice, Some(1));
+    /// assert_eq!(bob, None);
+    /// # }
+    /// ```
+    pub fn start_sweeper<K, V, F>(&self, every: Duration, select: F) -> Sweeper
+    where
+        K: Ord,
+        F: Fn(&mut T) -> &mut Expiring<K, V> + Send + 'static,
+    {
+        let (stop, stopped) = channel::<()>();
+        let db = self.clone();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(every) {
+                if let Err(_e) = db.access_mut(|t| select(t).remove_expired()) {
+                    trace_event!(error = %_e, "failed to sweep expired entries");
+                }
+            }
+        });
+
+        Sweeper {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
@@ -0,0 +1,469 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+use serde_json::ser::{PrettyFormatter, Serializer};
+
+use errors::*;
+use mvdb::{Inner, Mvdb};
+
+/// A range of bytes within a document
+type Span = (usize, usize);
+
+/// A JSON value within a document, and where its parts are
+struct Node {
+    start: usize,
+    end: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Scalar,
+
+    /// The elements, and the whitespace before the closing bracket
+    Array(Vec<Entry>, Span),
+
+    /// The members, and the whitespace before the closing brace
+    Object(Vec<Entry>, Span),
+}
+
+/// An element of an array or a member of an object, with the whitespace and
+/// punctuation around it. For elements, `key` and `sep` are empty
+struct Entry {
+    lead: Span,
+    key: Span,
+    sep: Span,
+    value: Node,
+    trail: Span,
+}
+
+/// A minimal JSON parser which records where each value is, rather than
+/// what it is
+struct Parser<'a> {
+    text: &'a [u8],
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&self, mut pos: usize) -> usize {
+        while pos < self.text.len() && (self.text[pos] as char).is_ascii_whitespace() {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn byte(&self, pos: usize) -> Result<u8> {
+        match self.text.get(pos) {
+            Some(&b) => Ok(b),
+            None => bail!("Unexpected end of document"),
+        }
+    }
+
+    fn value(&self, start: usize) -> Result<Node> {
+        match self.byte(start)? {
+            b'{' => self.container(start, b'}', true),
+            b'[' => self.container(start, b']', false),
+            b'"' => Ok(Node {
+                start,
+                end: self.string(start)?,
+                kind: Kind::Scalar,
+            }),
+            _ => {
+                let mut end = start;
+                while end < self.text.len() && !b",]} \t\r\n".contains(&self.text[end]) {
+                    end += 1;
+                }
+                Ok(Node {
+                    start,
+                    end,
+                    kind: Kind::Scalar,
+                })
+            }
+        }
+    }
+
+    /// Find the end of the string starting at `start`
+    fn string(&self, start: usize) -> Result<usize> {
+        let mut pos = start + 1;
+        loop {
+            match self.byte(pos)? {
+                b'\\' => pos += 2,
+                b'"' => return Ok(pos + 1),
+                _ => pos += 1,
+            }
+        }
+    }
+
+    fn container(&self, start: usize, close: u8, object: bool) -> Result<Node> {
+        let mut entries = Vec::new();
+        let mut lead_start = start + 1;
+        let mut pos = self.skip_ws(lead_start);
+
+        let closing = if self.byte(pos)? == close {
+            (lead_start, pos)
+        } else {
+            loop {
+                let lead = (lead_start, pos);
+                let (key, sep, value_start) = if object {
+                    let key_end = self.string(pos)?;
+                    let colon = self.skip_ws(key_end);
+                    if self.byte(colon)? != b':' {
+                        bail!("Expected ':' in object");
+                    }
+                    let value_start = self.skip_ws(colon + 1);
+                    ((pos, key_end), (key_end, value_start), value_start)
+                } else {
+                    ((pos, pos), (pos, pos), pos)
+                };
+
+                let value = self.value(value_start)?;
+                let after = self.skip_ws(value.end);
+                let value_end = value.end;
+                match self.byte(after)? {
+                    b',' => {
+                        entries.push(Entry {
+                            lead,
+                            key,
+                            sep,
+                            value,
+                            trail: (value_end, after),
+                        });
+                        lead_start = after + 1;
+                        pos = self.skip_ws(lead_start);
+                    }
+                    b if b == close => {
+                        entries.push(Entry {
+                            lead,
+                            key,
+                            sep,
+                            value,
+                            trail: (value_end, value_end),
+                        });
+                        pos = after;
+                        break (value_end, after);
+                    }
+                    _ => bail!("Expected ',' or closing bracket"),
+                }
+            }
+        };
+
+        Ok(Node {
+            start,
+            end: pos + 1,
+            kind: match object {
+                true => Kind::Object(entries, closing),
+                false => Kind::Array(entries, closing),
+            },
+        })
+    }
+}
+
+/// Edits a template document so that it holds new contents, keeping the
+/// layout of any parts of the template that are unchanged
+struct Editor<'a> {
+    text: &'a str,
+    pretty: bool,
+    unit: String,
+    between: &'a str,
+}
+
+impl<'a> Editor<'a> {
+    fn slice(&self, span: Span) -> &'a str {
+        &self.text[span.0..span.1]
+    }
+
+    /// The whitespace after a comma on the same line, as used between the
+    /// entries of the first array or object in `node` with more than one
+    fn between(&self, node: &Node) -> Option<&'a str> {
+        let entries = match node.kind {
+            Kind::Scalar => return None,
+            Kind::Array(ref entries, _) | Kind::Object(ref entries, _) => entries,
+        };
+        entries
+            .iter()
+            .skip(1)
+            .map(|entry| self.slice(entry.lead))
+            .find(|lead| !lead.contains('\n'))
+            .or_else(|| entries.iter().filter_map(|entry| self.between(&entry.value)).next())
+    }
+
+    /// The whitespace at the start of the line containing `pos`
+    fn indent_at(&self, pos: usize) -> &'a str {
+        let line = self.text[..pos].rfind('\n').map_or(0, |i| i + 1);
+        let rest = &self.text[line..];
+        let len = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        &rest[..len]
+    }
+
+    /// Serialize a value which is not in the template, to be placed at the
+    /// given indentation
+    fn fresh(&self, value: &Value, indent: &str) -> Result<String> {
+        if !self.pretty {
+            return serde_json::to_string(value).chain_err(|| "Failed to serialize");
+        }
+        let mut out = Vec::new();
+        let formatter = PrettyFormatter::with_indent(self.unit.as_bytes());
+        value
+            .serialize(&mut Serializer::with_formatter(&mut out, formatter))
+            .chain_err(|| "Failed to serialize")?;
+        let out = String::from_utf8(out).chain_err(|| "Failed to serialize")?;
+        Ok(out.replace('\n', &format!("\n{}", indent)))
+    }
+
+    fn render(&self, node: &Node, new: &Value) -> Result<String> {
+        let raw = self.slice((node.start, node.end));
+        let old: Value = serde_json::from_str(raw).chain_err(|| "Deserialize error")?;
+        if old == *new {
+            return Ok(raw.into());
+        }
+
+        let indent = self.indent_at(node.start);
+        match (&node.kind, new) {
+            (Kind::Object(entries, closing), Value::Object(map)) if !entries.is_empty() => {
+                let mut parts = Vec::new();
+                let mut seen = Vec::new();
+                for (i, entry) in entries.iter().enumerate() {
+                    let key: String = serde_json::from_str(self.slice(entry.key))
+                        .chain_err(|| "Deserialize error")?;
+                    if let Some(value) = map.get(&key) {
+                        parts.push((Some(i), self.entry(entry, value)?));
+                    }
+                    seen.push(key);
+                }
+
+                let last = &entries[entries.len() - 1];
+                for (key, value) in map.iter().filter(|&(k, _)| !seen.contains(k)) {
+                    let value = self.fresh(value, self.indent_at(last.value.start))?;
+                    let key = serde_json::to_string(key).chain_err(|| "Failed to serialize")?;
+                    parts.push((None, format!("{}{}{}", key, self.slice(last.sep), value)));
+                }
+                match parts.is_empty() {
+                    true => self.fresh(new, indent),
+                    false => Ok(format!("{{{}{}}}", self.join(entries, parts), self.slice(*closing))),
+                }
+            }
+            (Kind::Array(entries, closing), Value::Array(items)) if !entries.is_empty() => {
+                let mut parts = Vec::new();
+                for (i, (entry, value)) in entries.iter().zip(items).enumerate() {
+                    parts.push((Some(i), self.entry(entry, value)?));
+                }
+
+                let last = &entries[entries.len() - 1];
+                for value in items.iter().skip(entries.len()) {
+                    parts.push((None, self.fresh(value, self.indent_at(last.value.start))?));
+                }
+                match parts.is_empty() {
+                    true => self.fresh(new, indent),
+                    false => Ok(format!("[{}{}]", self.join(entries, parts), self.slice(*closing))),
+                }
+            }
+            _ => self.fresh(new, indent),
+        }
+    }
+
+    /// Join rendered entries with commas, placing the right whitespace before
+    /// each. Each part is tagged with the index of the template entry it came
+    /// from, if any. The first entry follows the opening bracket rather than
+    /// a comma, so its whitespace only suits the first part, and the others
+    /// take theirs from a later entry where there is one. On a single line,
+    /// the whitespace used elsewhere in the document is followed instead
+    fn join(&self, entries: &[Entry], parts: Vec<(Option<usize>, String)>) -> String {
+        let first = self.slice(entries[0].lead);
+        let between = match entries.len() {
+            1 if !first.contains('\n') => self.between,
+            n => self.slice(entries[n - 1].lead),
+        };
+        let mut out = Vec::new();
+        for (n, (index, part)) in parts.into_iter().enumerate() {
+            let lead = match (n, index) {
+                (0, _) => first,
+                (_, Some(i)) if i > 0 => self.slice(entries[i].lead),
+                _ => between,
+            };
+            out.push(format!("{}{}", lead, part));
+        }
+        out.join(",")
+    }
+
+    /// Render an entry with its new value, leaving out the whitespace before
+    /// it, which depends on where it ends up
+    fn entry(&self, entry: &Entry, value: &Value) -> Result<String> {
+        Ok(format!(
+            "{}{}{}{}",
+            self.slice(entry.key),
+            self.slice(entry.sep),
+            self.render(&entry.value, value)?,
+            self.slice(entry.trail),
+        ))
+    }
+}
+
+/// Edit a JSON document so that it holds new contents, changing only the
+/// values that differ, and keeping the order of existing keys along with
+/// all whitespace around unchanged values. New keys are added at the end of
+/// their object
+pub(crate) fn edit_in_place(template: &str, new: &Value) -> Result<String> {
+    let parser = Parser {
+        text: template.as_bytes(),
+    };
+    let start = parser.skip_ws(0);
+    let root = parser.value(start)?;
+    if parser.skip_ws(root.end) != template.len() {
+        bail!("Unexpected content after document");
+    }
+
+    // Use the indentation of the first indented line for any new values
+    let unit = template
+        .lines()
+        .skip(1)
+        .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+        .find(|indent| !indent.is_empty())
+        .unwrap_or("  ");
+
+    let mut editor = Editor {
+        text: template,
+        pretty: template[root.start..root.end].contains('\n'),
+        unit: unit.into(),
+        between: "",
+    };
+    editor.between = editor.between(&root).unwrap_or("");
+    Ok(format!(
+        "{}{}{}",
+        &template[..root.start],
+        editor.render(&root, new)?,
+        &template[root.end..]
+    ))
+}
+
+impl<T> Inner<T> {
+    /// Edit the last written document to hold serialized contents that are
+    /// about to be written to storage, if formatting is being preserved
+    pub(crate) fn with_layout(&mut self, contents: String) -> Result<String> {
+        let edited = match self.layout {
+            None => return Ok(contents),
+            Some(ref layout) => {
+                let new: Value = serde_json::from_str(&contents).chain_err(|| "Deserialize error")?;
+                edit_in_place(layout, &new)?
+            }
+        };
+        self.layout = Some(edited.clone());
+        Ok(edited)
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Keep the formatting of the file, such as one formatted by hand, when
+    /// writing. Rather than writing the contents in a canonical layout, the
+    /// existing document is edited so that only the values which changed are
+    /// rewritten, and the order of keys is kept. New keys are added at the end
+    /// of their object, following the layout of their neighbours
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Config { name: String, port: u16 }
+    ///
+    /// # fn main() {
+    /// let storage = MemoryStorage::with_contents(b"{\n    \"port\": 80,\n    \"name\":   \"demo\"\n}\n");
+    ///
+    /// let my_data: Mvdb<Config> = Mvdb::with_storage(storage.clone())
+    ///     .expect("Could not load storage");
+    /// my_data.preserve_formatting().expect("Could not load storage");
+    ///
+    /// my_data.access_mut(|cfg| cfg.port = 8080)
+    ///     .expect("Failed to access file");
+    ///
+    /// let contents = storage.contents().unwrap();
+    /// assert_eq!(contents, b"{\n    \"port\": 8080,\n    \"name\":   \"demo\"\n}\n".to_vec());
+    /// # }
+    /// ```
+    ///
+    /// Removed keys are dropped along with their whitespace, and the other
+    /// keys keep theirs. An object which is emptied is written as `{}`, and
+    /// refilled in the layout of the rest of the document:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # fn main() {
+    /// let storage = MemoryStorage::with_contents(b"{\n  \"a\": 1,\n  \"b\": {\n    \"c\": 2\n  }\n}");
+    /// let my_data: Mvdb<serde_json::Value> = Mvdb::with_storage(storage.clone()).unwrap();
+    /// my_data.preserve_formatting().unwrap();
+    ///
+    /// my_data.access_mut(|doc| *doc = json!({"b": {}})).unwrap();
+    /// assert_eq!(storage.contents().unwrap(), b"{\n  \"b\": {}\n}".to_vec());
+    ///
+    /// my_data.access_mut(|doc| *doc = json!({"b": {"d": [3]}})).unwrap();
+    /// assert_eq!(storage.contents().unwrap(), b"{\n  \"b\": {\n    \"d\": [\n      3\n    ]\n  }\n}".to_vec());
+    /// # }
+    /// ```
+    ///
+    /// Arrays shrink and grow in place, and new elements and keys are
+    /// separated in the way of the entries after the first. Compact documents
+    /// stay compact, and keys which need escaping are matched by their value:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # fn main() {
+    /// let storage = MemoryStorage::with_contents(br#"{"a\"b": 1, "list": [1, 2, 3]}"#);
+    /// let my_data: Mvdb<serde_json::Value> = Mvdb::with_storage(storage.clone()).unwrap();
+    /// my_data.preserve_formatting().unwrap();
+    ///
+    /// my_data.access_mut(|doc| *doc = json!({"list": [4, 5]})).unwrap();
+    /// assert_eq!(storage.contents().unwrap(), br#"{"list": [4, 5]}"#.to_vec());
+    ///
+    /// my_data.access_mut(|doc| *doc = json!({"list": [4, 5, 6, 7], "a\"b": {"c": 2}})).unwrap();
+    /// assert_eq!(storage.contents().unwrap(), br#"{"list": [4, 5, 6, 7], "a\"b": {"c":2}}"#.to_vec());
+    ///
+    /// let storage = MemoryStorage::with_contents(b"[\n    1,\n    2\n]\n");
+    /// let my_data: Mvdb<serde_json::Value> = Mvdb::with_storage(storage.clone()).unwrap();
+    /// my_data.preserve_formatting().unwrap();
+    ///
+    /// my_data.access_mut(|doc| *doc = json!([2, 3, {"c": 4}])).unwrap();
+    /// assert_eq!(storage.contents().unwrap(), b"[\n    2,\n    3,\n    {\n        \"c\": 4\n    }\n]\n".to_vec());
+    /// # }
+    /// ```
+    pub fn preserve_formatting(&self) -> Result<()> {
+        let stored = String::from_utf8(self.storage.read_all()?)
+            .chain_err(|| "Deserialize error")?;
+        serde_json::from_str::<Value>(&stored).chain_err(|| "Deserialize error")?;
+
+        let mut inner = self.lock()?;
+        inner.layout = Some(stored);
+        Ok(())
+    }
+}
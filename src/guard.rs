@@ -0,0 +1,276 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use lock::LockGuard;
+use mvdb::{Change, Mvdb};
+
+/// Read-only access to the contents of an `Mvdb`, held until dropped
+///
+/// Like the closure passed to `access`, no other handle may access the
+/// contents while this guard is held, so it should be dropped promptly.
+pub struct ReadGuard<'a, T: 'a> {
+    guard: LockGuard<'a, T>,
+}
+
+/// Read-only access to one part of the contents of an `Mvdb`, held until dropped
+///
+/// This allows large parts of the contents to be read without cloning them.
+pub struct MappedReadGuard<'a, T: 'a, U: ?Sized> {
+    guard: ReadGuard<'a, T>,
+    map: Box<dyn Fn(&T) -> &U + 'a>,
+}
+
+/// Writable access to the contents of an `Mvdb`, held until dropped
+///
+/// When the guard is dropped, the contents are checked for changes and
+/// written to the file, as with `access_mut`. Any error during this is
+/// ignored, so use `commit` to find out whether the changes were persisted.
+/// If the guard is dropped while the thread is panicking, nothing is written.
+pub struct WriteGuard<'a, T>
+where
+    T: Serialize + DeserializeOwned + 'a,
+{
+    db: &'a Mvdb<T>,
+    guard: Option<LockGuard<'a, T>>,
+    before: Option<(String, u64)>,
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Obtain read-only access to the database contents until the returned
+    /// guard is dropped. Contents are accessed in-memory only, and will not
+    /// re-read from the storage file, or cause any writes
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let db = my_data.read().expect("Failed to access file");
+    /// if db.bar.is_empty() {
+    ///     println!("foo: {}", db.foo);
+    /// }
+    /// # }
+    /// ```
+    pub fn read(&self) -> Result<ReadGuard<'_, T>> {
+        let guard = self.lock()?;
+        self.stats.reads.fetch_add(1, Ordering::Relaxed);
+        Ok(ReadGuard { guard })
+    }
+
+    /// Obtain read-only access to one part of the database contents until the
+    /// returned guard is dropped. This allows large parts of the contents to be
+    /// read without cloning them out of a closure passed to `access`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, multiple: Vec<String> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let multiple = my_data.map_read(|db| &db.multiple)
+    ///     .expect("Failed to access file");
+    ///
+    /// for item in multiple.iter() {
+    ///     println!("item: {}", item);
+    /// }
+    /// # }
+    /// ```
+    pub fn map_read<'a, U, F>(&'a self, map: F) -> Result<MappedReadGuard<'a, T, U>>
+    where
+        U: ?Sized,
+        F: Fn(&T) -> &U + 'a,
+    {
+        Ok(self.read()?.map(map))
+    }
+
+    /// Obtain writable access to the database contents until the returned
+    /// guard is dropped or committed. If the contents have changed by then,
+    /// the database will be written to the file
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::errors::*;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, count: u32 }
+    /// # fn run(my_data: &Mvdb<DemoData>) -> Result<()> {
+    /// let mut db = my_data.write()?;
+    /// db.count = "42".parse().chain_err(|| "Not a number")?;
+    /// db.foo = "New Value".into();
+    /// db.commit()?;
+    /// # Ok(())
+    /// # }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// # run(&my_data).unwrap();
+    /// # assert_eq!(my_data.access(|db| db.count).unwrap(), 42);
+    /// # }
+    /// ```
+    ///
+    /// Changes are not written if the thread panics while holding the guard:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # use std::panic::{self, AssertUnwindSafe};
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { name: String }
+    /// # fn main() {
+    /// # let storage = MemoryStorage::new();
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), storage.clone()).unwrap();
+    /// let result = panic::catch_unwind(AssertUnwindSafe(|| {
+    ///     let mut db = my_data.write().unwrap();
+    ///     db.name = "half-done".into();
+    ///     panic!("interrupted");
+    /// }));
+    ///
+    /// assert!(result.is_err());
+    /// assert_eq!(storage.contents().unwrap(), br#"{"name":""}"#.to_vec());
+    /// # }
+    /// ```
+    pub fn write(&self) -> Result<WriteGuard<'_, T>> {
+        let mut guard = self.lock()?;
+        let before = self.begin_locked(&mut guard)?;
+        Ok(WriteGuard {
+            db: self,
+            guard: Some(guard),
+            before: Some(before),
+        })
+    }
+}
+
+impl<'a, T> ReadGuard<'a, T> {
+    /// Narrow the guard to one part of the contents
+    pub fn map<U, F>(self, map: F) -> MappedReadGuard<'a, T, U>
+    where
+        U: ?Sized,
+        F: Fn(&T) -> &U + 'a,
+    {
+        MappedReadGuard {
+            guard: self,
+            map: Box::new(map),
+        }
+    }
+}
+
+impl<'a, T> Deref for ReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.visible()
+    }
+}
+
+impl<'a, T, U: ?Sized> Deref for MappedReadGuard<'a, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        (self.map)(&self.guard)
+    }
+}
+
+impl<'a, T> WriteGuard<'a, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Release the guard, writing the contents to the file if they have
+    /// changed. Returns whether the contents were written
+    pub fn commit(mut self) -> Result<bool> {
+        self.commit_inner()
+    }
+
+    fn commit_inner(&mut self) -> Result<bool> {
+        match (self.guard.take(), self.before.take()) {
+            (Some(mut guard), Some(before)) => {
+                self.db.commit_locked(&mut guard, before, Change::default())
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+impl<'a, T> Deref for WriteGuard<'a, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self.guard {
+            Some(ref guard) => &guard.data,
+            None => unreachable!("guard is only released when consumed"),
+        }
+    }
+}
+
+impl<'a, T> DerefMut for WriteGuard<'a, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        match self.guard {
+            Some(ref mut guard) => &mut guard.data,
+            None => unreachable!("guard is only released when consumed"),
+        }
+    }
+}
+
+impl<'a, T> Drop for WriteGuard<'a, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn drop(&mut self) {
+        // Changes interrupted by a panic may be incomplete, so never persist them
+        if thread::panicking() {
+            return;
+        }
+        let _ = self.commit_inner();
+    }
+}
@@ -25,20 +25,21 @@
 //! It is not intended that users will want or need these, however they are exposed in case users
 //! find them interesting
 
-use std::io::prelude::*;
 use std::hash::{Hash, Hasher};
-use std::fs::File;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
 use std::collections::hash_map::DefaultHasher;
 use serde_json;
+use serde_json::Value;
 use errors::*;
+use storage::{FileStorage, Storage};
 
-/// Use the default hasher to obtain the hash of a serialized item
-pub fn hash_by_serialize<T>(data: &T, pretty: bool) -> Result<(String, u64)>
+/// Serialize an item, optionally in a "pretty-print" format
+pub fn serialize<T>(data: &T, pretty: bool) -> Result<String>
 where
     T: Serialize,
 {
@@ -47,13 +48,127 @@ where
         false => serde_json::to_string,
     };
 
+    serializer(data).chain_err(|| "Failed to serialize")
+}
+
+/// Use the default hasher to obtain the hash of a serialized item
+pub fn hash_by_serialize<T>(data: &T, pretty: bool) -> Result<(String, u64)>
+where
+    T: Serialize,
+{
     let mut hasher = DefaultHasher::new();
-    let serialized = serializer(data)
+    let serialized = serialize(data, pretty)
         .chain_err(|| "Failed to serialize for hashing")?;
     serialized.hash(&mut hasher);
     Ok((serialized, hasher.finish()))
 }
 
+/// The number of milliseconds between the Unix epoch and the given time,
+/// or zero if the time is before the epoch. Times too far in the future
+/// to represent saturate at `u64::MAX`
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs().saturating_mul(1000).saturating_add(u64::from(d.subsec_millis())))
+        .unwrap_or(0)
+}
+
+/// Set the value at a [JSON Pointer](https://tools.ietf.org/html/rfc6901) path
+/// within a document
+///
+/// If the path does not exist, but its parent is an object, the value will be
+/// inserted into that object. If the parent is an array and the last segment
+/// of the path is `-`, the value will be appended to that array.
+pub fn set_pointer(doc: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    if pointer.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    if let Some(slot) = doc.pointer_mut(pointer) {
+        *slot = value;
+        return Ok(());
+    }
+
+    let split = match pointer.rfind('/') {
+        Some(split) => split,
+        None => bail!(ErrorKind::PathNotFound(pointer.into())),
+    };
+    let key = pointer[split + 1..].replace("~1", "/").replace("~0", "~");
+
+    match doc.pointer_mut(&pointer[..split]) {
+        Some(&mut Value::Object(ref mut map)) => {
+            map.insert(key, value);
+        }
+        Some(&mut Value::Array(ref mut array)) if key == "-" => {
+            array.push(value);
+        }
+        _ => bail!(ErrorKind::PathNotFound(pointer.into())),
+    }
+    Ok(())
+}
+
+/// Check that the value set at a path by `set_pointer` is still present
+/// once the document has been deserialized into `data`. Values at paths
+/// that `data` has no field for are dropped during deserialization, and
+/// are reported as an `ErrorKind::PathNotFound`
+pub(crate) fn check_pointer<T>(data: &T, pointer: &str, value: &Value) -> Result<()>
+where
+    T: Serialize,
+{
+    let doc = serde_json::to_value(data).chain_err(|| "Failed to serialize")?;
+    let kept = match pointer.ends_with("/-") {
+        // The value was appended to an array
+        true => doc.pointer(&pointer[..pointer.len() - 2])
+            .and_then(|array| array.as_array())
+            .and_then(|array| array.last()),
+        false => doc.pointer(pointer),
+    };
+
+    match kept {
+        Some(kept) if same_value(kept, value) => Ok(()),
+        _ => bail!(ErrorKind::PathNotFound(pointer.into())),
+    }
+}
+
+/// Whether a value survived deserialization, allowing for numbers changing
+/// representation and `null` fields being skipped. Any fields added while
+/// deserializing, such as defaults, are ignored
+fn same_value(kept: &Value, value: &Value) -> bool {
+    match (kept, value) {
+        (Value::Number(kept), Value::Number(value)) => {
+            kept == value || kept.as_f64() == value.as_f64()
+        }
+        (Value::Array(kept), Value::Array(value)) => {
+            kept.len() == value.len() && kept.iter().zip(value).all(|(k, v)| same_value(k, v))
+        }
+        (Value::Object(kept), Value::Object(value)) => {
+            value.iter().all(|(key, value)| match kept.get(key) {
+                Some(kept) => same_value(kept, value),
+                None => value.is_null(),
+            })
+        }
+        _ => kept == value,
+    }
+}
+
+/// Fill in any fields missing from a document with those from `defaults`
+///
+/// Objects are merged recursively, so fields missing from nested objects are
+/// also filled in. Any other values already present in the document, including
+/// arrays and `null`, are left as they are.
+pub fn fill_defaults(doc: &mut Value, defaults: Value) {
+    if let (&mut Value::Object(ref mut map), Value::Object(defaults)) = (doc, defaults) {
+        for (key, default) in defaults {
+            match map.get_mut(&key) {
+                Some(value) => fill_defaults(value, default),
+                None => {
+                    map.insert(key, default);
+                }
+            }
+        }
+    }
+}
+
 /// Attempt to load the contents of a serialized file to a `T`
 ///
 /// If anything goes wrong (file not available, schema mismatch),
@@ -62,11 +177,19 @@ pub fn just_load<T>(path: &Path) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let mut file = File::open(path)
-        .chain_err(|| format!("Failed to open file: {:?}", &path))?;
-    let mut contents = String::new();
-    let _ = file.read_to_string(&mut contents);
-    serde_json::from_str(&contents).chain_err(|| "Deserialize error")
+    just_load_from(&FileStorage::new(path))
+}
+
+/// Attempt to load the serialized contents of a `Storage` to a `T`
+///
+/// If anything goes wrong (storage not available, schema mismatch),
+/// an error will be returned
+pub fn just_load_from<T>(storage: &dyn Storage) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let contents = storage.read_all()?;
+    serde_json::from_slice(&contents).chain_err(|| "Deserialize error")
 }
 
 /// Attempt to write the contents of a `T` to a serialized file
@@ -77,24 +200,24 @@ pub fn just_write<T>(contents: &T, path: &Path, pretty: bool) -> Result<()>
 where
     T: Serialize,
 {
-    let serializer = match pretty {
-        true => serde_json::to_string_pretty,
-        false => serde_json::to_string,
-    };
-
-    just_write_string(&serializer(contents)
-        .chain_err(|| "Failed to serialize")?, path)
+    just_write_to(contents, &FileStorage::new(path), pretty)
 }
 
+/// Attempt to write the contents of a `T` to a `Storage`
+///
+/// If anything goes wrong (storage not writable, serialization failed),
+/// an error will be returned
+pub fn just_write_to<T>(contents: &T, storage: &dyn Storage, pretty: bool) -> Result<()>
+where
+    T: Serialize,
+{
+    storage.write_all(serialize(contents, pretty)?.as_bytes())
+}
 
 /// Attempt to write the contents to a serialized file
 ///
 /// Useful when the contents have already been serialized
 pub fn just_write_string(contents: &str, path: &Path) -> Result<()>
 {
-    let mut file = File::create(path)
-        .chain_err(|| format!("Failed to create file: {:?}", path))?;
-    let _ = file.write_all(contents.as_bytes())
-        .chain_err(|| "Failed to write to file")?;
-    Ok(())
-}
\ No newline at end of file
+    FileStorage::new(path).write_all(contents.as_bytes())
+}
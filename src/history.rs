@@ -0,0 +1,329 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+
+use errors::*;
+use helpers::{serialize, unix_millis};
+use mvdb::{Change, Mvdb};
+use storage::{FileStorage, Storage};
+
+/// Limits on how much version history is kept. Limits that are `None` are
+/// not enforced, and the most recent version is always kept
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The maximum number of versions to keep
+    pub max_versions: Option<usize>,
+
+    /// The maximum age of versions to keep
+    pub max_age: Option<Duration>,
+
+    /// The maximum size of the history, in bytes
+    pub max_bytes: Option<u64>,
+}
+
+/// A version of the database contents kept in the history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// The generation of the contents when this version was written
+    pub generation: u64,
+
+    /// The time this version was written
+    pub timestamp: SystemTime,
+}
+
+/// An append-only record of every version of the database contents,
+/// stored as one JSON document per line
+pub(crate) struct HistoryStore {
+    storage: Box<dyn Storage>,
+    retention: Retention,
+}
+
+/// A single line of the history
+struct Record {
+    generation: u64,
+    timestamp: SystemTime,
+    contents: Value,
+    line: String,
+}
+
+impl Record {
+    fn new(generation: u64, contents: Value) -> Result<Self> {
+        let timestamp = SystemTime::now();
+        let millis = unix_millis(timestamp);
+        let line = serialize(&json!({
+            "generation": generation,
+            "timestamp_ms": millis,
+            "contents": contents,
+        }), false)?;
+
+        Ok(Self {
+            generation,
+            timestamp,
+            contents,
+            line,
+        })
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let mut value: Value = serde_json::from_str(line)
+            .chain_err(|| "Corrupt history entry")?;
+        let generation = value["generation"].as_u64();
+        let millis = value["timestamp_ms"].as_u64();
+
+        match (generation, millis, value.get_mut("contents")) {
+            (Some(generation), Some(millis), Some(contents)) => Ok(Self {
+                generation,
+                timestamp: UNIX_EPOCH + Duration::from_millis(millis),
+                contents: contents.take(),
+                line: line.to_string(),
+            }),
+            _ => bail!("Corrupt history entry"),
+        }
+    }
+
+    fn version(&self) -> Version {
+        Version {
+            generation: self.generation,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl HistoryStore {
+    /// Load every version currently in the history, oldest first
+    fn load(&self) -> Result<Vec<Record>> {
+        if !self.storage.exists()? {
+            return Ok(Vec::new());
+        }
+
+        let contents = self.storage.read_all()?;
+        String::from_utf8_lossy(&contents)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(Record::parse)
+            .collect()
+    }
+
+    /// Add serialized contents to the history as the given generation,
+    /// then enforce the retention limits
+    pub(crate) fn record(&self, generation: u64, contents: &str) -> Result<()> {
+        let contents = serde_json::from_str(contents)
+            .chain_err(|| "Deserialize error")?;
+        let record = Record::new(generation, contents)?;
+        self.storage.append(format!("{}\n", record.line).as_bytes())?;
+        self.enforce_retention()
+    }
+
+    /// Remove the oldest versions that fall outside of the retention limits
+    fn enforce_retention(&self) -> Result<()> {
+        if self.retention == Retention::default() {
+            return Ok(());
+        }
+
+        let mut records = self.load()?;
+        let before = records.len();
+
+        if let Some(max) = self.retention.max_versions {
+            let excess = records.len().saturating_sub(max.max(1));
+            records.drain(..excess);
+        }
+
+        if let Some(max_age) = self.retention.max_age {
+            let now = SystemTime::now();
+            let keep_from = records.iter()
+                .position(|r| now.duration_since(r.timestamp).unwrap_or_default() <= max_age)
+                .unwrap_or(records.len())
+                .min(records.len().saturating_sub(1));
+            records.drain(..keep_from);
+        }
+
+        if let Some(max_bytes) = self.retention.max_bytes {
+            let mut total: u64 = records.iter().map(|r| r.line.len() as u64 + 1).sum();
+            while total > max_bytes && records.len() > 1 {
+                total -= records.remove(0).line.len() as u64 + 1;
+            }
+        }
+
+        if records.len() != before {
+            self.write_records(&records)?;
+        }
+        Ok(())
+    }
+
+    /// Remove any version recorded as the given generation, such as one for
+    /// a change that was then rolled back
+    pub(crate) fn discard(&self, generation: u64) -> Result<()> {
+        let records = self.load()?;
+        if records.iter().all(|r| r.generation != generation) {
+            return Ok(());
+        }
+
+        let kept: Vec<Record> = records.into_iter()
+            .filter(|r| r.generation != generation)
+            .collect();
+        self.write_records(&kept)
+    }
+
+    /// Replace the entire history with the given versions
+    fn write_records(&self, records: &[Record]) -> Result<()> {
+        let mut contents = String::new();
+        for record in records {
+            contents.push_str(&record.line);
+            contents.push('\n');
+        }
+        self.storage.write_all(contents.as_bytes())
+    }
+
+    /// Find a single version in the history
+    fn find(&self, generation: u64) -> Result<Record> {
+        match self.load()?.into_iter().find(|r| r.generation == generation) {
+            Some(record) => Ok(record),
+            None => bail!(ErrorKind::VersionNotFound(generation)),
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Start keeping every version of the database contents in an append-only
+    /// history file, alongside the main file. Versions outside of the retention
+    /// limits are removed, oldest first.
+    ///
+    /// If the history already contains versions, the generation of the database
+    /// continues on from the most recent version. If the current contents do not
+    /// match the most recent version, they are added as a new version.
+    ///
+    /// No modification is persisted without being kept in the history. If a
+    /// version cannot be added, the previous contents are restored, both in the
+    /// file and in memory, and the Error is returned
+    pub fn enable_history(&self, path: &Path, retention: Retention) -> Result<()> {
+        self.enable_history_with_storage(FileStorage::new(path), retention)
+    }
+
+    /// Start keeping every version of the database contents in an append-only
+    /// history in the given `Storage`. See `enable_history` for details
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{Mvdb, Retention};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { port: u16 }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// let retention = Retention { max_versions: Some(100), ..Retention::default() };
+    /// my_data.enable_history_with_storage(MemoryStorage::new(), retention)
+    ///     .expect("Failed to access history");
+    ///
+    /// my_data.access_mut(|cfg| cfg.port = 8080).expect("Failed to access file");
+    /// my_data.access_mut(|cfg| cfg.port = 9090).expect("Failed to access file");
+    ///
+    /// let versions = my_data.history().expect("Failed to access history");
+    /// assert_eq!(versions.len(), 3);
+    ///
+    /// let old = my_data.at_version(versions[1].generation).expect("No such version");
+    /// assert_eq!(old.port, 8080);
+    ///
+    /// my_data.revert_to(versions[0].generation).expect("Failed to revert");
+    /// assert_eq!(my_data.access(|cfg| cfg.port).unwrap(), 0);
+    /// # }
+    /// ```
+    pub fn enable_history_with_storage<S>(&self, storage: S, retention: Retention) -> Result<()>
+    where
+        S: Storage + 'static,
+    {
+        let store = HistoryStore {
+            storage: Box::new(storage),
+            retention,
+        };
+
+        let mut inner = self.lock()?;
+        let current = serde_json::to_value(&inner.data)
+            .chain_err(|| "Failed to serialize")?;
+
+        match store.load()?.pop() {
+            Some(ref latest) if latest.contents == current => {
+                inner.generation = inner.generation.max(latest.generation);
+            }
+            latest => {
+                let latest = latest.map(|r| r.generation).unwrap_or(0);
+                inner.generation = inner.generation.max(latest) + 1;
+                store.record(inner.generation, &serialize(&current, false)?)?;
+            }
+        }
+
+        inner.history = Some(store);
+        Ok(())
+    }
+
+    /// List the versions kept in the history, oldest first
+    pub fn history(&self) -> Result<Vec<Version>> {
+        let inner = self.lock()?;
+        match inner.history {
+            Some(ref history) => Ok(history.load()?.iter().map(|r| r.version()).collect()),
+            None => bail!("History is not enabled"),
+        }
+    }
+
+    /// Obtain the database contents as they were at the given generation
+    pub fn at_version(&self, generation: u64) -> Result<T> {
+        let record = {
+            let inner = self.lock()?;
+            match inner.history {
+                Some(ref history) => history.find(generation)?,
+                None => bail!("History is not enabled"),
+            }
+        };
+        serde_json::from_value(record.contents).chain_err(|| "Deserialize error")
+    }
+
+    /// Restore the database contents to those of the given generation, and write
+    /// them to the file. The restored contents are recorded as a new version
+    pub fn revert_to(&self, generation: u64) -> Result<()> {
+        let mut inner = self.lock()?;
+        let record = match inner.history {
+            Some(ref history) => history.find(generation)?,
+            None => bail!("History is not enabled"),
+        };
+
+        let before = self.begin_locked(&mut inner)?;
+        inner.data = serde_json::from_value(record.contents)
+            .chain_err(|| "Deserialize error")?;
+        self.commit_locked(&mut inner, before, Change {
+            reason: Some("revert"),
+            ..Change::default()
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+use tiny_http;
+
+use errors::*;
+use mvdb::{Change, Mvdb};
+use patch::merge_patch;
+
+/// An HTTP request to be handled by `Mvdb::handle_http`, independent of any
+/// particular HTTP server
+#[derive(Debug, Clone, Copy)]
+pub struct HttpRequest<'a> {
+    /// The request method, such as `GET`
+    pub method: &'a str,
+
+    /// The value of the `If-Match` header, if any
+    pub if_match: Option<&'a str>,
+
+    /// The request body
+    pub body: &'a [u8],
+}
+
+/// The response to an `HttpRequest`. The body is always JSON
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    /// The status code, such as `200`
+    pub status: u16,
+
+    /// The value of the `ETag` header, if any
+    pub etag: Option<String>,
+
+    /// The response body
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn error(status: u16, message: &str) -> Self {
+        HttpResponse {
+            status,
+            etag: None,
+            body: json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+/// The entity tag of contents with the given hash. This is derived from the
+/// contents rather than the generation, which restarts with each process, so
+/// that a tag never matches different contents
+fn etag(hash: u64) -> String {
+    format!("\"{:016x}\"", hash)
+}
+
+/// The largest request body accepted by the standalone server, in bytes
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A standalone HTTP server for an `Mvdb`, started by `Mvdb::serve_http`.
+/// The server is stopped when the `HttpServer` is dropped
+pub struct HttpServer {
+    server: Arc<tiny_http::Server>,
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Handle an HTTP request for the database contents, so they may be
+    /// served by an existing HTTP server
+    ///
+    /// * `GET` returns the stored contents, without any overrides, so that
+    ///   they may be edited and sent back with `PUT`
+    /// * `PUT` replaces the contents with the request body
+    /// * `PATCH` applies the request body to the contents as a JSON Merge Patch
+    ///
+    /// Each response carries an `ETag` based on a hash of the contents, which
+    /// stays the same across restarts. If a `PUT` or `PATCH` has an `If-Match`
+    /// header that does not match the current `ETag`, the contents are left
+    /// unchanged and `412` is returned. Changes must pass any validators,
+    /// otherwise `422` is returned
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{HttpRequest, Mvdb};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let current = my_data.handle_http(&HttpRequest { method: "GET", if_match: None, body: b"" });
+    /// assert_eq!(current.status, 200);
+    ///
+    /// let patched = my_data.handle_http(&HttpRequest {
+    ///     method: "PATCH",
+    ///     if_match: current.etag.as_ref().map(|e| e.as_str()),
+    ///     body: br#"{"foo": "Patched"}"#,
+    /// });
+    /// assert_eq!(patched.status, 200);
+    ///
+    /// // The contents have changed since `current` was returned
+    /// let stale = my_data.handle_http(&HttpRequest {
+    ///     method: "PUT",
+    ///     if_match: current.etag.as_ref().map(|e| e.as_str()),
+    ///     body: br#"{"foo": "Replaced", "bar": []}"#,
+    /// });
+    /// assert_eq!(stale.status, 412);
+    /// # }
+    /// ```
+    ///
+    /// Overrides are never served, so a response may be sent back unchanged:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::{HttpRequest, Mvdb};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { port: u16 }
+    /// # fn main() {
+    /// # let storage = MemoryStorage::new();
+    /// # let my_data = Mvdb::new_with_storage(Config { port: 80 }, storage.clone()).unwrap();
+    /// my_data.set_override("/port", serde_json::json!(9999)).expect("Invalid override");
+    ///
+    /// let current = my_data.handle_http(&HttpRequest { method: "GET", if_match: None, body: b"" });
+    /// assert_eq!(current.body, r#"{"port":80}"#);
+    ///
+    /// let put = my_data.handle_http(&HttpRequest {
+    ///     method: "PUT",
+    ///     if_match: current.etag.as_ref().map(|e| e.as_str()),
+    ///     body: current.body.as_bytes(),
+    /// });
+    /// assert_eq!(put.status, 200);
+    /// assert_eq!(storage.contents().unwrap(), br#"{"port":80}"#.to_vec());
+    /// # }
+    /// ```
+    pub fn handle_http(&self, request: &HttpRequest) -> HttpResponse {
+        match request.method {
+            "GET" | "HEAD" => self.http_get(),
+            "PUT" => match serde_json::from_slice::<T>(request.body) {
+                Ok(data) => self.http_update(request.if_match, |_| Ok(data)),
+                Err(e) => HttpResponse::error(400, &e.to_string()),
+            },
+            "PATCH" => match serde_json::from_slice::<Value>(request.body) {
+                Ok(patch) => self.http_update(request.if_match, |data| {
+                    let mut doc = serde_json::to_value(data).chain_err(|| "Failed to serialize")?;
+                    merge_patch(&mut doc, &patch);
+                    serde_json::from_value(doc)
+                        .chain_err(|| ErrorKind::ValidationFailed("Modified contents do not match schema".into()))
+                }),
+                Err(e) => HttpResponse::error(400, &e.to_string()),
+            },
+            _ => HttpResponse::error(405, "Method not allowed"),
+        }
+    }
+
+    fn http_get(&self) -> HttpResponse {
+        let current = self.lock().and_then(|inner| self.hash_timed(&inner.data));
+        match current {
+            Ok((body, hash)) => HttpResponse {
+                status: 200,
+                etag: Some(etag(hash)),
+                body,
+            },
+            Err(e) => HttpResponse::error(500, &e.to_string()),
+        }
+    }
+
+    /// Replace the contents with those produced by `update`, if `if_match`
+    /// matches the current contents, then commit them as usual
+    fn http_update<F>(&self, if_match: Option<&str>, update: F) -> HttpResponse
+    where
+        F: FnOnce(&T) -> Result<T>,
+    {
+        trace_span!("mvdb::handle_http");
+        let updated = self.lock().and_then(|mut inner| {
+            let before = self.begin_locked(&mut inner)?;
+            match if_match {
+                Some(tag) if tag != "*" && tag != etag(before.1) => return Ok((before.1, None)),
+                _ => {}
+            }
+
+            inner.data = update(&inner.data)?;
+            let change = Change {
+                reason: Some("http"),
+                ..Change::default()
+            };
+            self.commit_locked(&mut inner, before, change)?;
+            let (body, hash) = self.hash_timed(&inner.data)?;
+            Ok((hash, Some(body)))
+        });
+
+        match updated {
+            Ok((hash, Some(body))) => HttpResponse {
+                status: 200,
+                etag: Some(etag(hash)),
+                body,
+            },
+            Ok((hash, None)) => HttpResponse {
+                etag: Some(etag(hash)),
+                ..HttpResponse::error(412, "Contents have changed since the given ETag")
+            },
+            Err(Error(ErrorKind::ValidationFailed(reason), _)) => HttpResponse::error(422, &reason),
+            Err(e) => HttpResponse::error(500, &e.to_string()),
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Serve the database contents over HTTP on the given address, as
+    /// described by `handle_http`, regardless of the request path. Use port
+    /// `0` to pick any free port, and `HttpServer::local_addr` to find out
+    /// which was picked. Request bodies larger than 16 MiB are refused with
+    /// `413`
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use std::path::Path;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8> }
+    /// # fn main() {
+    /// let my_data: Mvdb<DemoData> = Mvdb::from_file(Path::new("demo.json"))
+    ///     .expect("Failed to load file");
+    ///
+    /// let _server = my_data.serve_http("127.0.0.1:8080")
+    ///     .expect("Failed to start server");
+    /// # }
+    /// ```
+    pub fn serve_http<A: ToSocketAddrs>(&self, addr: A) -> Result<HttpServer> {
+        let server = match tiny_http::Server::http(addr) {
+            Ok(server) => Arc::new(server),
+            Err(e) => bail!("Failed to start HTTP server: {}", e),
+        };
+        let addr = match server.server_addr().to_ip() {
+            Some(addr) => addr,
+            None => bail!("Failed to start HTTP server: not listening on an IP address"),
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let db = self.clone();
+            let server = server.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    if let Ok(request) = server.recv() {
+                        if let Err(_e) = respond(&db, request) {
+                            trace_event!(error = %_e, "failed to respond to HTTP request");
+                        }
+                    }
+                }
+            })
+        };
+
+        Ok(HttpServer {
+            server,
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Handle a request received by the standalone server
+fn respond<T>(db: &Mvdb<T>, mut request: tiny_http::Request) -> Result<()>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let mut body = Vec::new();
+    Read::take(request.as_reader(), MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)
+        .chain_err(|| "Failed to read request")?;
+
+    let if_match = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("If-Match"))
+        .map(|h| h.value.as_str().to_string());
+    let method = request.method().as_str().to_string();
+
+    let response = match body.len() as u64 > MAX_BODY_BYTES {
+        true => HttpResponse::error(413, "Request body is too large"),
+        false => db.handle_http(&HttpRequest {
+            method: &method,
+            if_match: if_match.as_deref(),
+            body: &body,
+        }),
+    };
+
+    let mut reply = tiny_http::Response::from_string(response.body)
+        .with_status_code(response.status)
+        .with_header(header("Content-Type", "application/json"));
+    if let Some(ref tag) = response.etag {
+        reply = reply.with_header(header("ETag", tag));
+    }
+    request.respond(reply).chain_err(|| "Failed to send response")
+}
+
+fn header(field: &str, value: &str) -> tiny_http::Header {
+    // Both are valid ASCII, so this cannot fail
+    tiny_http::Header::from_bytes(field.as_bytes(), value.as_bytes())
+        .expect("invalid header")
+}
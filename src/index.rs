@@ -0,0 +1,263 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::{Inner, Mvdb};
+
+/// An index over the items of one collection within the contents of an `Mvdb`
+pub(crate) trait Index<T>: Send + Sync {
+    /// Bring the index up to date with the current contents
+    fn rebuild(&mut self, data: &T);
+
+    /// Clone every item with the given key into a `Vec`, returned as `Any`
+    /// so that the caller may recover the item type
+    fn lookup(&self, data: &T, key: &str) -> Box<dyn Any>;
+}
+
+/// An index of the positions of items in a slice, by key
+struct SliceIndex<I, K, C, E> {
+    collection: C,
+    key: E,
+    keys: Vec<String>,
+    positions: HashMap<String, Vec<usize>>,
+    _types: PhantomData<fn(&I) -> K>,
+}
+
+impl<T, I, K, C, E> Index<T> for SliceIndex<I, K, C, E>
+where
+    I: Clone + 'static,
+    K: ToString,
+    C: Fn(&T) -> &[I] + Send + Sync,
+    E: Fn(&I) -> K + Send + Sync,
+{
+    fn rebuild(&mut self, data: &T) {
+        let keys: Vec<String> = (self.collection)(data)
+            .iter()
+            .map(|item| (self.key)(item).to_string())
+            .collect();
+
+        // Only positions after the first changed key need updating, so that
+        // appending items does not recompute the whole index. Positions are
+        // kept in ascending order, so those being removed are always last
+        let unchanged = self.keys.iter().zip(&keys).take_while(|(old, new)| old == new).count();
+        for key in self.keys[unchanged..].iter().rev() {
+            if let Some(positions) = self.positions.get_mut(key) {
+                positions.pop();
+                if positions.is_empty() {
+                    self.positions.remove(key);
+                }
+            }
+        }
+        for (i, key) in keys.iter().enumerate().skip(unchanged) {
+            self.positions.entry(key.clone()).or_default().push(i);
+        }
+        self.keys = keys;
+    }
+
+    fn lookup(&self, data: &T, key: &str) -> Box<dyn Any> {
+        // Items are checked against the key, in case they have changed
+        // without the index being brought up to date
+        let items = (self.collection)(data);
+        let found: Vec<I> = match self.positions.get(key) {
+            Some(positions) => positions.iter()
+                .filter_map(|&i| items.get(i))
+                .filter(|item| (self.key)(item).to_string() == key)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        Box::new(found)
+    }
+}
+
+impl<T> Inner<T> {
+    /// Bring every index up to date with the contents seen by read-only accesses
+    pub(crate) fn rebuild_indexes(&mut self) {
+        let data = self.overlaid.as_ref().unwrap_or(&self.data);
+        for index in self.indexes.values_mut() {
+            index.rebuild(data);
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Declare an index over the items of a collection within the database
+    /// contents. `collection` selects the items, and `key` extracts the key
+    /// of each item. Indexes are kept in memory only. They are brought up to
+    /// date after each writable access that changes the serialized contents,
+    /// whether or not those could be written, and only the positions after
+    /// the first changed item are updated. Collections that are not
+    /// serialized, such as those marked `#[serde(skip)]`, should not be
+    /// indexed. Declaring an index with the same name as an existing index
+    /// replaces it
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize, Clone, Default)]
+    /// struct Record { name: String, team: String }
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Directory { records: Vec<Record> }
+    ///
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Directory::default(), MemoryStorage::new()).unwrap();
+    /// my_data.add_index("by_team", |d: &Directory| &d.records[..], |r: &Record| r.team.clone())
+    ///     .expect("Failed to access file");
+    ///
+    /// my_data.access_mut(|d| d.records.push(Record { name: "alice".into(), team: "ops".into() }))
+    ///     .expect("Failed to access file");
+    ///
+    /// let ops: Vec<Record> = my_data.lookup("by_team", "ops").expect("No such index");
+    /// assert_eq!(ops[0].name, "alice");
+    /// # }
+    /// ```
+    ///
+    /// Indexes follow items as they are added, changed and removed:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Clone, Default)]
+    /// # struct Record { name: String, team: String }
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Directory { records: Vec<Record> }
+    /// # fn record(name: &str, team: &str) -> Record { Record { name: name.into(), team: team.into() } }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Directory::default(), MemoryStorage::new()).unwrap();
+    /// my_data.add_index("by_team", |d: &Directory| &d.records[..], |r: &Record| r.team.clone())
+    ///     .expect("Failed to access file");
+    /// let team = |name: &str| -> Vec<String> {
+    ///     let found: Vec<Record> = my_data.lookup("by_team", name).expect("No such index");
+    ///     found.into_iter().map(|r| r.name).collect()
+    /// };
+    ///
+    /// my_data.access_mut(|d| d.records.extend(vec![record("a", "ops"), record("b", "dev")]))
+    ///     .expect("Failed to access file");
+    /// my_data.access_mut(|d| d.records.push(record("c", "ops"))).expect("Failed to access file");
+    /// assert_eq!(team("ops"), vec!["a", "c"]);
+    ///
+    /// my_data.access_mut(|d| d.records[0].team = "dev".into()).expect("Failed to access file");
+    /// assert_eq!(team("ops"), vec!["c"]);
+    /// assert_eq!(team("dev"), vec!["a", "b"]);
+    ///
+    /// my_data.access_mut(|d| { d.records.remove(1); }).expect("Failed to access file");
+    /// assert_eq!(team("dev"), vec!["a"]);
+    /// assert_eq!(team("ops"), vec!["c"]);
+    /// # }
+    /// ```
+    pub fn add_index<I, K, C, E>(&self, name: &str, collection: C, key: E) -> Result<()>
+    where
+        I: Clone + 'static,
+        K: ToString + 'static,
+        C: Fn(&T) -> &[I] + Send + Sync + 'static,
+        E: Fn(&I) -> K + Send + Sync + 'static,
+    {
+        let mut index = SliceIndex {
+            collection,
+            key,
+            keys: Vec::new(),
+            positions: HashMap::new(),
+            _types: PhantomData,
+        };
+
+        let mut inner = self.lock()?;
+        index.rebuild(inner.visible());
+        inner.indexes.insert(name.to_string(), Box::new(index));
+        Ok(())
+    }
+
+    /// Obtain a copy of every item with the given key, using the named index.
+    /// `I` must be the type of the items in the indexed collection.
+    ///
+    /// Lookups always reflect the contents in memory, even when a modification
+    /// could not be written to storage
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::errors::*;
+    /// # use mvdb::storage::{MemoryStorage, Storage, StorageMetadata};
+    /// # struct ReadOnly(MemoryStorage);
+    /// # impl Storage for ReadOnly {
+    /// #     fn read_all(&self) -> Result<Vec<u8>> { self.0.read_all() }
+    /// #     fn write_all(&self, _: &[u8]) -> Result<()> { Err("Storage is read-only".into()) }
+    /// #     fn exists(&self) -> Result<bool> { self.0.exists() }
+    /// #     fn metadata(&self) -> Result<StorageMetadata> { self.0.metadata() }
+    /// # }
+    /// #[derive(Serialize, Deserialize, Clone, Default)]
+    /// struct Record { name: String }
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Directory { records: Vec<Record> }
+    ///
+    /// # fn main() {
+    /// # let storage = MemoryStorage::new();
+    /// # storage.write_all(br#"{"records":[{"name":"a"},{"name":"b"}]}"#).unwrap();
+    /// // Storage that can be read, but not written
+    /// let my_data: Mvdb<Directory> = Mvdb::with_storage(ReadOnly(storage))
+    ///     .expect("Failed to load storage");
+    /// my_data.add_index("by_name", |d: &Directory| &d.records[..], |r: &Record| r.name.clone())
+    ///     .expect("Failed to access file");
+    ///
+    /// assert!(my_data.access_mut(|d| d.records.clear()).is_err());
+    ///
+    /// let found: Vec<Record> = my_data.lookup("by_name", "b").expect("No such index");
+    /// assert!(found.is_empty());
+    /// # }
+    /// ```
+    pub fn lookup<I, K>(&self, name: &str, key: K) -> Result<Vec<I>>
+    where
+        I: Clone + 'static,
+        K: ToString,
+    {
+        let inner = self.lock()?;
+        let found = match inner.indexes.get(name) {
+            Some(index) => index.lookup(inner.visible(), &key.to_string()),
+            None => bail!(ErrorKind::IndexNotFound(name.into())),
+        };
+
+        match found.downcast::<Vec<I>>() {
+            Ok(found) => Ok(*found),
+            Err(_) => bail!("Index {:?} does not contain items of the requested type", name),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::env;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+
+use errors::*;
+use helpers::{check_pointer, set_pointer};
+use mvdb::{Inner, Mvdb};
+
+/// The layer which supplied a value seen by `access`, as returned by
+/// `Mvdb::provenance`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layer {
+    /// The persisted contents
+    Stored,
+
+    /// The environment variable with the given name
+    Env(String),
+
+    /// A value set with `Mvdb::set_override`
+    Override,
+}
+
+/// Values layered over the persisted contents, which are seen by read-only
+/// accesses but never persisted
+#[derive(Default)]
+pub(crate) struct Layers {
+    /// Overrides read from environment variables: the variable name, the
+    /// path it maps to, and its unparsed value
+    env: Vec<(String, String, String)>,
+
+    /// Overrides set explicitly, by path. These take precedence over `env`
+    values: BTreeMap<String, Value>,
+
+    /// Increases by one each time the layers change
+    pub(crate) version: u64,
+}
+
+impl Layers {
+    fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.values.is_empty()
+    }
+
+    /// Build a copy of `data` with the layers applied, or `None` if there
+    /// are no layers. Layers whose path does not resolve within `data` are
+    /// skipped, so that they never prevent changes to the stored contents
+    pub(crate) fn overlay<T>(&self, data: &T) -> Result<Option<T>>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let mut doc = serde_json::to_value(data).chain_err(|| "Failed to serialize")?;
+        for (_, pointer, raw) in &self.env {
+            let value = env_value(&doc, pointer, raw);
+            let _ = set_pointer(&mut doc, pointer, value);
+        }
+        for (pointer, value) in &self.values {
+            let _ = set_pointer(&mut doc, pointer, value.clone());
+        }
+
+        let overlaid = serde_json::from_value(doc)
+            .chain_err(|| ErrorKind::ValidationFailed("Overrides do not match schema".into()))?;
+        Ok(Some(overlaid))
+    }
+}
+
+/// Whether `pointer` refers to the value at `layer`, or to a value within it
+fn covers(layer: &str, pointer: &str) -> bool {
+    pointer == layer || (pointer.starts_with(layer) && pointer[layer.len()..].starts_with('/'))
+}
+
+/// Map an environment variable name, without its prefix, to a path. Nested
+/// fields are separated by a double underscore, so `NETWORK__PORT` becomes
+/// `/network/port`
+fn env_pointer(name: &str) -> String {
+    name.split("__")
+        .map(|segment| format!("/{}", segment.to_lowercase()))
+        .collect()
+}
+
+/// Parse the value of an environment variable as JSON, unless the value it
+/// replaces is a string, or it is not valid JSON
+fn env_value(doc: &Value, pointer: &str, raw: &str) -> Value {
+    match doc.pointer(pointer) {
+        Some(&Value::String(_)) => Value::String(raw.into()),
+        _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.into())),
+    }
+}
+
+impl<T> Inner<T> {
+    /// The contents as seen by read-only accesses, including any overrides
+    pub(crate) fn visible(&self) -> &T {
+        self.overlaid.as_ref().unwrap_or(&self.data)
+    }
+}
+
+impl<T> Inner<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Recompute the contents seen by read-only accesses, and the indexes
+    /// over them, after either the contents or the layers may have changed.
+    /// This never fails, as overrides must not prevent changes to the stored
+    /// contents: if the overrides no longer match the schema, they are not
+    /// applied until the contents or the overrides change again
+    pub(crate) fn refresh(&mut self) {
+        let overlaid = self.layers.overlay(&self.data);
+        if overlaid.is_err() {
+            trace_event!("overrides do not match schema, not applying them");
+        }
+        self.overlaid = overlaid.unwrap_or_default();
+        self.rebuild_indexes();
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Override parts of the contents with environment variables starting with
+    /// `prefix`, replacing any previously read. The rest of each name is mapped
+    /// to a path, with nested fields separated by a double underscore, so
+    /// `APP_NETWORK__PORT` overrides `/network/port` for the prefix `APP_`.
+    ///
+    /// Overrides are seen by `access` and every other read-only access,
+    /// including views, derived values, indexes and batches, but are never
+    /// persisted. `access_mut` and other modifications see and persist only
+    /// the stored contents, as does the HTTP endpoint, so that its responses
+    /// may be edited and sent back. Environment variables that do not map to
+    /// a place in the contents are ignored.
+    ///
+    /// While any override is set, read-only accesses see a copy of the contents
+    /// rebuilt from their serialized form after each writable access. Fields
+    /// that are not serialized, such as those marked `#[serde(skip)]`, are seen
+    /// by read-only accesses with their default values, and are only available
+    /// through `access_mut` and other writable accesses
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{Layer, Mvdb};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # use std::env;
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Network { host: String, port: u16 }
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Config { name: String, network: Network }
+    ///
+    /// # fn main() {
+    /// # let storage = MemoryStorage::new();
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), storage.clone()).unwrap();
+    /// env::set_var("DEMO_NETWORK__PORT", "8080");
+    /// my_data.override_from_env("DEMO_").expect("Invalid override");
+    ///
+    /// my_data.access_mut(|cfg| cfg.network.port = 80)
+    ///     .expect("Failed to access file");
+    ///
+    /// // The override is seen, but the stored value is persisted
+    /// assert_eq!(my_data.access(|cfg| cfg.network.port).unwrap(), 8080);
+    /// assert!(String::from_utf8(storage.contents().unwrap()).unwrap().contains("80}"));
+    ///
+    /// let layer = my_data.provenance("/network/port").unwrap();
+    /// assert_eq!(layer, Layer::Env("DEMO_NETWORK__PORT".into()));
+    /// # }
+    /// ```
+    pub fn override_from_env(&self, prefix: &str) -> Result<()> {
+        let vars = env::vars()
+            .filter(|(name, _)| name.starts_with(prefix) && name.len() > prefix.len())
+            .map(|(name, raw)| {
+                let pointer = env_pointer(&name[prefix.len()..]);
+                (name, pointer, raw)
+            })
+            .collect();
+
+        self.update_layers(|layers, _| {
+            layers.env = vars;
+            Ok(())
+        })
+    }
+
+    /// Override the value at a [JSON Pointer](https://tools.ietf.org/html/rfc6901)
+    /// path, such as one given on the command line. This takes precedence over
+    /// environment variables, and like them is never persisted. See
+    /// `override_from_env` for how overrides are seen.
+    ///
+    /// The path must name a field of `T` that is present in the current
+    /// contents, otherwise an `ErrorKind::PathNotFound` is returned. If later
+    /// changes to the contents remove it, the override is skipped until it
+    /// resolves again
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Network { port: u16 }
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Config { network: Option<Network> }
+    ///
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// my_data.access_mut(|cfg| cfg.network = Some(Network { port: 80 }))
+    ///     .expect("Failed to access file");
+    /// my_data.set_override("/network/port", serde_json::json!(9000)).expect("Invalid override");
+    ///
+    /// let port = my_data.access(|cfg| cfg.network.as_ref().map(|n| n.port)).unwrap();
+    /// assert_eq!(port, Some(9000));
+    ///
+    /// // Paths that do not name a field are rejected
+    /// assert!(my_data.set_override("/network/prot", serde_json::json!(9001)).is_err());
+    ///
+    /// // The override does not prevent the stored contents from changing
+    /// my_data.access_mut(|cfg| cfg.network = None).expect("Failed to access file");
+    /// assert!(my_data.access(|cfg| cfg.network.is_none()).unwrap());
+    /// # }
+    /// ```
+    pub fn set_override(&self, pointer: &str, value: Value) -> Result<()> {
+        self.update_layers(|layers, data| {
+            let mut doc = serde_json::to_value(data).chain_err(|| "Failed to serialize")?;
+            set_pointer(&mut doc, pointer, value.clone())?;
+            let overlaid: T = serde_json::from_value(doc)
+                .chain_err(|| ErrorKind::ValidationFailed("Overrides do not match schema".into()))?;
+
+            check_pointer(&overlaid, pointer, &value)?;
+            layers.values.insert(pointer.into(), value);
+            Ok(())
+        })
+    }
+
+    /// Remove the override previously set for a path, if any
+    pub fn remove_override(&self, pointer: &str) -> Result<()> {
+        self.update_layers(|layers, _| {
+            layers.values.remove(pointer);
+            Ok(())
+        })
+    }
+
+    /// Find which layer supplied the value at a [JSON Pointer](https://tools.ietf.org/html/rfc6901)
+    /// path, as seen by `access`. A value within an overridden value is
+    /// considered to be supplied by that override
+    pub fn provenance(&self, pointer: &str) -> Result<Layer> {
+        let inner = self.lock()?;
+        let doc = serde_json::to_value(inner.visible()).chain_err(|| "Failed to serialize")?;
+        if doc.pointer(pointer).is_none() {
+            bail!(ErrorKind::PathNotFound(pointer.into()));
+        }
+
+        if inner.layers.values.keys().any(|layer| covers(layer, pointer)) {
+            return Ok(Layer::Override);
+        }
+
+        // Later variables take precedence, as they are applied last
+        let env = inner.layers.env.iter().rev().find(|&(_, layer, _)| covers(layer, pointer));
+        match env {
+            Some((name, _, _)) => Ok(Layer::Env(name.clone())),
+            None => Ok(Layer::Stored),
+        }
+    }
+
+    /// Modify the layers, and recompute the contents seen by read-only
+    /// accesses. If the result does not match the schema, the layers are
+    /// left unchanged
+    fn update_layers<F>(&self, action: F) -> Result<()>
+    where
+        F: FnOnce(&mut Layers, &T) -> Result<()>,
+    {
+        let mut guard = self.lock()?;
+        let inner = &mut *guard;
+        let previous = (inner.layers.env.clone(), inner.layers.values.clone());
+
+        let updated = action(&mut inner.layers, &inner.data)
+            .and_then(|_| inner.layers.overlay(&inner.data));
+        match updated {
+            Ok(overlaid) => {
+                inner.overlaid = overlaid;
+                inner.rebuild_indexes();
+                inner.layers.version += 1;
+                Ok(())
+            }
+            Err(e) => {
+                inner.layers.env = previous.0;
+                inner.layers.values = previous.1;
+                Err(e)
+            }
+        }
+    }
+}
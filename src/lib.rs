@@ -47,19 +47,21 @@
 //!
 //! ## Put it in your project
 //!
-//! ```
+//! ```toml
 //! # in Cargo.toml:
 //! [dependencies]
 //! mvdb = "0.2"
+//! ```
 //!
-//! # in your Rust code:
+//! ```rust,ignore
+//! // in your Rust code:
 //! extern crate mvdb;
 //! ```
 //!
 //! ## Example
 //!
-//! ```rust
-//! [macro_use] extern crate serde_derive;
+//! ```rust,no_run
+//! #[macro_use] extern crate serde_derive;
 //! extern crate serde;
 //! extern crate mvdb;
 //!
@@ -125,15 +127,321 @@
 //! the trait, then you can use the `from_file_or_default` method. This will attempt to load the file, or if that fails, a new file
 //! will be created with default data. This is useful for configuration files with sane defaults, or when the file is expected to
 //! be generated on first run
+//!
+//! ## Storage Backends
+//!
+//! By default, `mvdb` persists data to a file on disk. Every constructor that takes a path also has a
+//! variant that takes a [`Storage`](storage/trait.Storage.html) instead, such as `Mvdb::with_storage`.
+//! `MemoryStorage` keeps the data in memory only, which is useful for tests, and the `Storage` trait
+//! may be implemented to store data elsewhere, such as in a row of another database or in a partition
+//! of embedded flash.
+//!
+//! ```rust
+//! # #[macro_use] extern crate serde_derive;
+//! # extern crate mvdb;
+//! use mvdb::Mvdb;
+//! use mvdb::storage::MemoryStorage;
+//!
+//! # #[derive(Deserialize, Serialize, Default)]
+//! # struct DemoData { foo: String }
+//! # fn main() {
+//! let storage = MemoryStorage::new();
+//! let my_data: Mvdb<DemoData> = Mvdb::with_storage_or_default(storage.clone())
+//!     .expect("Could not write to storage");
+//!
+//! my_data.access_mut(|db| db.foo = "bar".into())
+//!     .expect("Failed to access storage");
+//!
+//! assert_eq!(storage.contents().unwrap(), br#"{"foo":"bar"}"#.to_vec());
+//! # }
+//! ```
+//!
+//! ## Read-Only Handles
+//!
+//! Any clone of an `Mvdb` may modify the contents. To hand out access that can never cause a write, such as to a plugin or
+//! a worker thread, use `read_only()` to obtain an `MvdbReader`, which only offers read access. Tooling that inspects
+//! existing files can use `Mvdb::open_read_only`, which will never create or write to the file.
+//!
+//! ## Views
+//!
+//! Components that only care about one part of the stored data can be given an `MvdbView` instead of the whole database.
+//! A view is created with `project`, given one closure selecting the part for reading and one selecting it for writing.
+//! Views share the same lock and storage as the `Mvdb` they were created from, and changes made through a view are
+//! persisted as usual.
+//!
+//! ```rust
+//! # #[macro_use] extern crate serde_derive;
+//! # extern crate mvdb;
+//! # use mvdb::Mvdb;
+//! # use mvdb::storage::MemoryStorage;
+//! # #[derive(Serialize, Deserialize, Default)]
+//! # struct Network { port: u16 }
+//! # #[derive(Serialize, Deserialize, Default)]
+//! # struct Config { network: Network }
+//! # fn main() {
+//! # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+//! let network = my_data.project(|t| &t.network, |t| &mut t.network);
+//! network.access_mut(|net| net.port = 8080)
+//!     .expect("Failed to access file");
+//! # }
+//! ```
+//!
+//! ## Command Line Tool
+//!
+//! With the `cli` feature enabled, `mvdb` also provides a command line tool for inspecting and editing stored files,
+//! without needing to open them in an editor. Install it with `cargo install mvdb --features cli`, then run `mvdb help`
+//! for a list of commands. Values are addressed using [JSON Pointer](https://tools.ietf.org/html/rfc6901) paths, and
+//! all writes follow the same atomic write rules as the library.
+//!
+//! ```text
+//! $ mvdb get demo.json /just_one/foo
+//! "thisisatest"
+//! $ mvdb set demo.json /just_one/foo '"tacos"'
+//! ```
+//!
+//! ## Dynamic Access
+//!
+//! Sometimes it is useful to read or modify a single value without having the concrete type in scope, such as in an
+//! admin endpoint. `get_path` and `set_path` address values within the serialized contents using
+//! [JSON Pointer](https://tools.ietf.org/html/rfc6901) paths, like `/network/port`. Modifications made with `set_path` must
+//! still deserialize into the stored type, and the path must name one of its fields, otherwise they are rejected and
+//! nothing is written.
+//!
+//! ## Patches
+//!
+//! Partial updates may be applied as a [JSON Patch](https://tools.ietf.org/html/rfc6902) with `apply_patch`, or as a
+//! [JSON Merge Patch](https://tools.ietf.org/html/rfc7396) with `apply_merge_patch`. As with `set_path`, the patched
+//! contents must still deserialize into the stored type, and a patch is either applied completely or not at all. `diff`
+//! produces a JSON Patch that would transform the current contents into another value.
+//!
+//! ## Validation
+//!
+//! Validators may be registered with `add_validator` to reject contents that violate your invariants, such as an empty
+//! token or a port of zero. Validators run after every modification, and if any of them reject the new contents, the
+//! changes are rolled back, nothing is written, and an `ErrorKind::ValidationFailed` error is returned. The current
+//! contents, such as those just loaded from a file, are checked when the validator is registered, and produce the same
+//! error if invalid.
+//!
+//! By default, rejected changes are rolled back by deserializing the last serialized contents, which resets any fields
+//! marked `#[serde(skip)]` to their default values. If `T` implements `Clone`, call `rollback_by_clone` to roll back to a
+//! clone taken at the start of each modification instead, which keeps those fields.
+//!
+//! ## Undo and Redo
+//!
+//! Calling `enable_undo` with a limit starts keeping a bounded history of the contents before each persisted
+//! modification. `undo` and `redo` restore those contents and write them to the file, subject to any registered
+//! validators. Modifications made with `access_mut_labeled` carry a label, such as "Rename", which can be listed with
+//! `undo_labels` and `redo_labels` to describe what will be undone or redone.
+//!
+//! ## Version History
+//!
+//! Each time modified contents are written, the generation of the database increases by one, which can be read with
+//! `generation`. Calling `enable_history` with a path keeps every version of the contents, along with its generation and
+//! timestamp, in an append-only file alongside the main file. `history` lists the kept versions, `at_version` loads the
+//! contents as they were at a given generation, and `revert_to` restores them as a new version. A `Retention` may limit
+//! the history by number of versions, age, or size, with the oldest versions removed first. If a version cannot be added
+//! to the history, the modification is rolled back, both in the file and in memory, and an error is returned.
+//!
+//! ## Audit Log
+//!
+//! Calling `enable_audit` with a path records every modification in a separate, append-only audit file. Each entry holds
+//! the actor and reason for the modification, its timestamp and generation, and a JSON Patch of what changed. Use
+//! `access_mut_as` to give an actor and reason for a single modification, or `with_actor` to obtain a handle whose
+//! modifications are all recorded as made by one actor. Entries are chained together by their SHA-256 hashes, and
+//! `verify_audit_log` will report any entry that has been modified or reordered. No modification is persisted without
+//! being audited: if its entry cannot be appended, the modification is rolled back, both in the file and in memory, and
+//! an error is returned.
+//!
+//! ## Metrics and Tracing
+//!
+//! `stats` returns counters shared by all handles to a database: the number of reads, writes, writes skipped because the
+//! contents were unchanged, bytes written, and how often the lock was already held, along with histograms of time spent
+//! waiting for the lock, serializing, and writing. With the `tracing` feature enabled, accesses are also wrapped in
+//! [`tracing`](https://docs.rs/tracing) spans, with events for lock acquisition and writes.
+//!
+//! ## Nested Access and Timeouts
+//!
+//! Accessing a database from within another access to it on the same thread, even through a clone, view, or reader,
+//! would deadlock. Instead, the nested access returns an `ErrorKind::Reentrant` error. To avoid waiting indefinitely for
+//! an access made on another thread, `try_access` and `try_access_mut` return `ErrorKind::WouldBlock` immediately if the
+//! lock is held, and `access_timeout` and `access_mut_timeout` do so after the given timeout.
+//!
+//! ## Guards
+//!
+//! As an alternative to closures, `read` returns a guard that dereferences to the contents, and `write` returns a guard
+//! that may also modify them. When a write guard is dropped, the contents are checked for changes and written to the file,
+//! just as after `access_mut`. Errors cannot be reported when a guard is dropped, so call `commit` on the guard to find
+//! out whether the changes were persisted. A write guard dropped while its thread is panicking writes nothing, so that
+//! incomplete changes are never persisted. As with closures, no other access can be made while a guard is held.
+//!
+//! `map_read` returns a read guard narrowed to one part of the contents, so that large fields can be read without
+//! cloning them out of an `access` closure.
+//!
+//! ## Batches
+//!
+//! Calling `access_mut` several times in a row may write the file several times. Instead, `batch` holds the lock while a
+//! closure makes any number of accesses through the given `Batch`, then checks for changes and writes the file at most
+//! once, when the closure returns `Ok`. If the closure returns an error, every change made during the batch is rolled
+//! back, and nothing is written. As with rejected changes, unless `rollback_by_clone` has been called, rolling back resets
+//! any fields marked `#[serde(skip)]` to their default values. A batch that did not change the serialized contents is never
+//! rolled back.
+//!
+//! ## Indexes
+//!
+//! Finding items in a large collection by scanning it on every access can be slow. `add_index` declares a named index,
+//! given one closure that selects a collection (as a slice) within the contents, and another that extracts the key of
+//! each item. Indexes are kept in memory only: they are built when declared, and brought up to date incrementally after
+//! every writable access that changes the serialized contents, even if those could not be written. `lookup` returns a
+//! copy of every item with a given key.
+//!
+//! ## Queries
+//!
+//! `query` starts a query over a collection within the contents, such as a `Vec` or a map, selected by a closure. Items
+//! can be narrowed with `filter`, ordered with `order_by` or `order_by_desc`, and paged through with `offset` and `limit`.
+//! `run` returns a copy of each matching item, or a `(key, value)` pair for maps. `page` also returns a cursor for an
+//! ordered query, which can be passed to `after` to continue from the last item of the previous page.
+//!
+//! ## Derived Values
+//!
+//! Aggregates computed from the contents on every read can be costly. `derive` returns a `Derived` value, which runs the
+//! given closure when first requested and caches the result, shared between clones and threads. The cached result is
+//! only computed again after modified contents have been written, as tracked by the database generation.
+//!
+//! ## Expiring Entries
+//!
+//! An `Expiring` map holds entries which each expire at a deadline, such as tokens or sessions. Expired entries are hidden
+//! by `get`, `iter` and the other methods of the map, and each deadline is stored alongside its value, so deadlines
+//! survive a restart. Expired entries remain in storage until `remove_expired` is called, or until they are removed by a
+//! background thread started with `start_sweeper`, which persists the removals and stops when the returned `Sweeper` is
+//! dropped.
+//!
+//! ## Replication
+//!
+//! `publish` listens on a TCP address and streams the contents to each `Follower` that connects, starting with a full
+//! snapshot, then a JSON Patch for each later write, one JSON object per line. A `Follower` keeps a read-only copy of the
+//! contents in memory, and optionally in a file or other `Storage` of its own. If the connection is lost, the follower
+//! keeps its last contents and reconnects in the background, catching up with a fresh snapshot. `wait_for_generation`
+//! waits until a given generation of the primary's contents has been applied.
+//!
+//! ## HTTP Endpoint
+//!
+//! With the optional `http` feature enabled, `serve_http` serves the contents as JSON on a local address, for inspection
+//! and administration. `GET` returns the contents, `PUT` replaces them, and `PATCH` applies a JSON Merge Patch to them.
+//! Changes are validated and written like any other modification. Each response carries an `ETag` based on a hash of
+//! the contents, and a `PUT` or `PATCH` with a stale `If-Match` header is refused, as is a request body larger than 16 MiB.
+//! To serve the contents from an existing HTTP server instead, pass each request to `handle_http`.
+//!
+//! ## Overrides
+//!
+//! Parts of the stored contents can be overridden without persisting the overrides, for example per deployment.
+//! `override_from_env` reads environment variables with a given prefix, mapping the rest of each name to a path, with
+//! nested fields separated by a double underscore, so `APP_NETWORK__PORT` overrides `/network/port`. `set_override` sets
+//! the value at a path directly, such as one given on the command line, and takes precedence over environment variables.
+//! Every read-only access sees the contents with overrides applied: `access`, `read`, views, derived values, queries,
+//! index lookups and `Batch::access`. `access_mut` and other modifications see and persist only the stored contents, and
+//! are never blocked by an override: one whose path no longer resolves is skipped until it does. The HTTP endpoint also
+//! serves only the stored contents, so that a response can be edited and sent back without persisting any overrides.
+//! `provenance` reports which layer supplied the value at a path.
+//!
+//! **Note:** while any override is set, read-only accesses see a copy of the contents rebuilt from their serialized form
+//! after each modification, so fields marked `#[serde(skip)]` are seen with their default values. Such fields remain
+//! available through `access_mut`.
+//!
+//! ## Adding Fields
+//!
+//! Loading a file written before a field was added to `T` fails, unless that field is marked `#[serde(default)]`. Instead,
+//! `from_file_with_defaults` fills in any fields missing from the file, at any depth, with those of `T::default()`, then
+//! writes the completed contents back to the file, keeping any fields `T` does not know. Those unknown fields are only kept
+//! in this first write: the next modification drops them, unless `preserve_unknown_fields` is also called just after
+//! loading. If the file does not exist, the default contents are written. Unlike `from_file_or_default`, a file which still
+//! does not match the schema is left unchanged, and an Error is returned.
+//!
+//! ## Unknown Fields
+//!
+//! When a file written by a newer version of a program is loaded by an older one, fields which are not part of the older
+//! `T` are dropped, and would be lost on the next write. Calling `preserve_unknown_fields` just after loading keeps any
+//! such fields, at every level of nested objects, and includes them in every later write. This allows several versions
+//! of a program to share one file safely.
+//!
+//! ## Preserving Formatting
+//!
+//! Each write normally replaces the file with the contents in a canonical layout, which loses the key order and
+//! formatting of a file edited by hand. Calling `preserve_formatting` just after loading makes each later write edit the
+//! existing document instead: only the values which changed are rewritten, the order of keys is kept, and new keys are
+//! added at the end of their object, following the layout of their neighbours.
 
 #[macro_use]
 extern crate error_chain;
 extern crate serde;
 // TODO: generic across all serializers/deserializers?
+#[macro_use]
 extern crate serde_json;
+extern crate sha2;
+#[cfg(feature = "http")]
+extern crate tiny_http;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+#[macro_use]
+mod stats;
+pub use stats::*;
 
 pub mod helpers;
 pub mod errors;
+pub mod patch;
+pub mod storage;
 
 mod mvdb;
 pub use mvdb::*;
+
+mod reader;
+pub use reader::*;
+
+mod view;
+pub use view::*;
+
+mod audit;
+pub use audit::*;
+
+mod batch;
+pub use batch::*;
+
+mod derive;
+pub use derive::*;
+
+mod expiring;
+pub use expiring::*;
+
+mod format;
+
+mod guard;
+pub use guard::*;
+
+mod history;
+pub use history::*;
+
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "http")]
+pub use http::*;
+
+mod index;
+
+mod layers;
+pub use layers::*;
+
+mod lock;
+
+mod pointer;
+
+mod query;
+pub use query::*;
+
+mod replicate;
+pub use replicate::*;
+
+mod undo;
+
+mod unknown;
+
+mod validate;
@@ -0,0 +1,206 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::MutexGuard;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::{Change, Inner, Mvdb};
+
+thread_local! {
+    /// The locks currently held by this thread, identified by address
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How long to wait for the lock, if it is already held
+#[derive(Clone, Copy)]
+pub(crate) enum Wait {
+    Forever,
+    Never,
+    Until(Instant),
+}
+
+impl Wait {
+    /// Wait for at most `timeout`. Timeouts too long to represent as a
+    /// deadline wait forever
+    pub(crate) fn timeout(timeout: Duration) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => Wait::Until(deadline),
+            None => Wait::Forever,
+        }
+    }
+}
+
+/// The lock protecting the contents of an `Mvdb`, which is tracked as held
+/// by the current thread until it is dropped
+pub(crate) struct LockGuard<'a, T: 'a> {
+    guard: MutexGuard<'a, Inner<T>>,
+    key: usize,
+}
+
+impl<'a, T> LockGuard<'a, T> {
+    pub(crate) fn new(guard: MutexGuard<'a, Inner<T>>, key: usize) -> Self {
+        HELD.with(|held| held.borrow_mut().push(key));
+        Self { guard, key }
+    }
+}
+
+impl<'a, T> Deref for LockGuard<'a, T> {
+    type Target = Inner<T>;
+
+    fn deref(&self) -> &Inner<T> {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for LockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Inner<T> {
+        &mut self.guard
+    }
+}
+
+impl<'a, T> Drop for LockGuard<'a, T> {
+    fn drop(&mut self) {
+        let key = self.key;
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|&k| k == key) {
+                held.remove(i);
+            }
+        });
+    }
+}
+
+/// Check whether the current thread already holds the lock with the given address
+pub(crate) fn held_by_current_thread(key: usize) -> bool {
+    HELD.with(|held| held.borrow().contains(&key))
+}
+
+/// Wait for a short, increasing amount of time, up to `deadline`. Returns
+/// `false` if the deadline has already passed
+pub(crate) fn backoff(attempt: u32, deadline: Instant) -> bool {
+    let now = Instant::now();
+    if now >= deadline {
+        return false;
+    }
+
+    let delay = Duration::from_micros(50 << attempt.min(7));
+    ::std::thread::sleep(delay.min(deadline - now));
+    true
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Provide atomic read-only access to the database contents via a closure,
+    /// as with `access`. If another handle currently holds the lock, an
+    /// `ErrorKind::WouldBlock` is returned immediately, rather than waiting
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::errors::ErrorKind;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let other = my_data.clone();
+    ///
+    /// let nested = my_data.access_mut(|_| other.try_access(|db| db.foo.clone()))
+    ///     .expect("Failed to access file");
+    ///
+    /// // Nested accesses on the same thread are reported, rather than deadlocking
+    /// match nested {
+    ///     Err(e) => assert!(matches!(e.kind(), ErrorKind::Reentrant)),
+    ///     Ok(_) => unreachable!(),
+    /// }
+    /// # }
+    /// ```
+    pub fn try_access<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.access_within(Wait::Never, action)
+    }
+
+    /// Provide atomic writable access to the database contents via a closure,
+    /// as with `access_mut`. If another handle currently holds the lock, an
+    /// `ErrorKind::WouldBlock` is returned immediately, rather than waiting
+    pub fn try_access_mut<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.access_mut_within(Wait::Never, Change::default(), action)
+    }
+
+    /// Provide atomic read-only access to the database contents via a closure,
+    /// as with `access`. If the lock cannot be acquired within `timeout`, an
+    /// `ErrorKind::WouldBlock` is returned. Timeouts too long to represent
+    /// wait as long as necessary
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # use std::time::Duration;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let foo = my_data.access_timeout(Duration::from_millis(100), |db| db.foo.clone())
+    ///     .expect("Lock was held for too long");
+    ///
+    /// my_data.access_mut_timeout(Duration::MAX, |db| db.foo = foo + "!")
+    ///     .expect("Failed to access file");
+    /// # }
+    /// ```
+    pub fn access_timeout<F, R>(&self, timeout: Duration, action: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.access_within(Wait::timeout(timeout), action)
+    }
+
+    /// Provide atomic writable access to the database contents via a closure,
+    /// as with `access_mut`. If the lock cannot be acquired within `timeout`,
+    /// an `ErrorKind::WouldBlock` is returned. Timeouts too long to represent
+    /// wait as long as necessary
+    pub fn access_mut_timeout<F, R>(&self, timeout: Duration, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.access_mut_within(Wait::timeout(timeout), Change::default(), action)
+    }
+}
@@ -20,22 +20,65 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::ops::Deref;
-use std::ops::DerefMut;
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
 
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
 
 use errors::*;
 use helpers::*;
+use audit::AuditLog;
+use history::HistoryStore;
+use index::Index;
+use layers::Layers;
+use lock::{backoff, held_by_current_thread, LockGuard, Wait};
+use replicate::Update;
+use stats::{Stats, StatsCounters};
+use storage::{FileStorage, Storage};
+use undo::UndoHistory;
+use validate::Validator;
 
 /// Minimum Viable Psuedo Database
 pub struct Mvdb<T> {
-    inner: Arc<Mutex<T>>,
-    file_path: PathBuf,
-    pretty: bool,
+    inner: Arc<Mutex<Inner<T>>>,
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) pretty: bool,
+    pub(crate) actor: Option<Arc<str>>,
+    pub(crate) stats: Arc<StatsCounters>,
+}
+
+/// The contents of an `Mvdb`, along with any state that is shared between,
+/// and protected by the same lock as, all handles to those contents
+pub(crate) struct Inner<T> {
+    pub(crate) data: T,
+    pub(crate) validators: Vec<Validator<T>>,
+    pub(crate) undo: Option<UndoHistory>,
+    pub(crate) history: Option<HistoryStore>,
+    pub(crate) audit: Option<AuditLog>,
+    pub(crate) generation: u64,
+    pub(crate) indexes: HashMap<String, Box<dyn Index<T>>>,
+    pub(crate) replicas: Vec<Sender<Update>>,
+    pub(crate) layers: Layers,
+    pub(crate) overlaid: Option<T>,
+    pub(crate) unknown: Option<Value>,
+    pub(crate) layout: Option<String>,
+    pub(crate) cloner: Option<fn(&T) -> T>,
+    pub(crate) snapshot: Option<T>,
+}
+
+/// A description of a modification, as recorded by the undo history and the audit log
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Change<'a> {
+    pub(crate) label: Option<&'a str>,
+    pub(crate) actor: Option<&'a str>,
+    pub(crate) reason: Option<&'a str>,
 }
 
 /// Implement `Clone` manually, otherwise Rust expects `T` to also impl `Clone`,
@@ -44,8 +87,10 @@ impl<T> Clone for Mvdb<T> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
-            file_path: self.file_path.clone(),
+            storage: self.storage.clone(),
             pretty: self.pretty,
+            actor: self.actor.clone(),
+            stats: self.stats.clone(),
         }
     }
 }
@@ -59,15 +104,24 @@ where
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # impl DemoData { fn new() -> Self { Self::default() } }
+    /// # fn main() {
     /// let data = DemoData::new();
     /// let file = Path::new("demo.json");
     ///
     /// let my_data = Mvdb::new(data, &file)
     ///     .expect("Could not write to file");
+    /// # }
     /// ```
     pub fn new(data: T, path: &Path) -> Result<Self> {
-        Self::new_inner(data, path, false)
+        Self::new_inner(data, Arc::new(FileStorage::new(path)), false)
     }
 
     /// Create a new `Mvdb` given data to contain and path to store.
@@ -77,15 +131,24 @@ where
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # impl DemoData { fn new() -> Self { Self::default() } }
+    /// # fn main() {
     /// let data = DemoData::new();
     /// let file = Path::new("demo_pretty.json");
     ///
     /// let my_data = Mvdb::new_pretty(data, &file)
     ///     .expect("Could not write to file");
+    /// # }
     /// ```
     pub fn new_pretty(data: T, path: &Path) -> Result<Self> {
-        Self::new_inner(data, path, true)
+        Self::new_inner(data, Arc::new(FileStorage::new(path)), true)
     }
 
     /// Create a new `Mvdb` given just the path. If the file does
@@ -94,13 +157,21 @@ where
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
     /// let file = Path::new("demo.json");
-    /// let my_data: Mvdb<DemoData> = Mvdb::from_file(&file);
+    /// let my_data: Mvdb<DemoData> = Mvdb::from_file(&file)
     ///     .expect("File does not exist, or schema mismatch");
+    /// # }
     /// ```
     pub fn from_file(path: &Path) -> Result<Self> {
-        Self::from_file_inner(path, false)
+        Self::from_storage_inner(Arc::new(FileStorage::new(path)), false)
     }
 
     /// Create a new `Mvdb` given just the path. If the file does
@@ -112,37 +183,136 @@ where
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
     /// let file = Path::new("demo_pretty.json");
-    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_pretty(&file);
+    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_pretty(&file)
     ///     .expect("File does not exist, or schema mismatch");
+    /// # }
     /// ```
     pub fn from_file_pretty(path: &Path) -> Result<Self> {
-        Self::from_file_inner(path, true)
+        Self::from_storage_inner(Arc::new(FileStorage::new(path)), true)
     }
 
-    /// Create a new `Mvdb` given data to contain and path to store.
-    /// File will be created and written to immediately
-    fn new_inner(data: T, path: &Path, pretty: bool) -> Result<Self> {
-        let new_self = Self::new_no_write(data, path, pretty);
-        new_self.write()?;
+    /// Create a new `Mvdb` given data to contain and a `Storage` to
+    /// persist it to. The data will be written to the storage immediately
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// let storage = MemoryStorage::new();
+    /// let my_data = Mvdb::new_with_storage(DemoData::default(), storage.clone())
+    ///     .expect("Could not write to storage");
+    ///
+    /// assert!(storage.contents().is_some());
+    /// # }
+    /// ```
+    pub fn new_with_storage<S>(data: T, storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::new_inner(data, Arc::new(storage), false)
+    }
+
+    /// Create a new `Mvdb` given data to contain and a `Storage` to
+    /// persist it to. The data will be written to the storage immediately,
+    /// using a "pretty-print" JSON format
+    pub fn new_with_storage_pretty<S>(data: T, storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::new_inner(data, Arc::new(storage), true)
+    }
+
+    /// Create a new `Mvdb` from the contents already held by a `Storage`.
+    /// If the storage is empty, or the contained data does not match the
+    /// schema of `T`, this will return an Error
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// let storage = MemoryStorage::with_contents(br#"{"foo":"a","bar":[],"baz":"b"}"#);
+    /// let my_data: Mvdb<DemoData> = Mvdb::with_storage(storage)
+    ///     .expect("Storage is empty, or schema mismatch");
+    /// # }
+    /// ```
+    pub fn with_storage<S>(storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::from_storage_inner(Arc::new(storage), false)
+    }
+
+    /// Create a new `Mvdb` from the contents already held by a `Storage`.
+    /// If the storage is empty, or the contained data does not match the
+    /// schema of `T`, this will return an Error. Subsequent writes will be
+    /// stored in a "pretty-print" JSON format
+    pub fn with_storage_pretty<S>(storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::from_storage_inner(Arc::new(storage), true)
+    }
+
+    /// Create a new `Mvdb` given data to contain and storage to use.
+    /// Storage will be written to immediately
+    fn new_inner(data: T, storage: Arc<dyn Storage>, pretty: bool) -> Result<Self> {
+        let new_self = Self::new_no_write(data, storage, pretty);
+        new_self.flush()?;
         Ok(new_self)
     }
 
-    /// Create a new `Mvdb` given just the path. If the file does
-    /// not exist, or the contained data does not match the schema
-    /// of `T`, this will return an Error
-    fn from_file_inner(path: &Path, pretty: bool) -> Result<Self> {
-        let contents = just_load(&path)?;
-        Ok(Self::new_no_write(contents, path, pretty))
+    /// Create a new `Mvdb` given just the storage. If the storage is
+    /// empty, or the contained data does not match the schema of `T`,
+    /// this will return an Error
+    pub(crate) fn from_storage_inner(storage: Arc<dyn Storage>, pretty: bool) -> Result<Self> {
+        let contents = just_load_from(&*storage)?;
+        Ok(Self::new_no_write(contents, storage, pretty))
     }
 
-    /// Create a new `Self`, but do not flush to file
-    fn new_no_write(data: T, path: &Path, pretty: bool) -> Self {
+    /// Create a new `Self`, but do not flush to storage
+    fn new_no_write(data: T, storage: Arc<dyn Storage>, pretty: bool) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(data)),
-            file_path: path.to_path_buf(),
-            pretty: pretty,
+            inner: Arc::new(Mutex::new(Inner {
+                data,
+                validators: Vec::new(),
+                undo: None,
+                history: None,
+                audit: None,
+                generation: 0,
+                indexes: HashMap::new(),
+                replicas: Vec::new(),
+                layers: Layers::default(),
+                overlaid: None,
+                unknown: None,
+                layout: None,
+                cloner: None,
+                snapshot: None,
+            })),
+            storage,
+            pretty,
+            actor: None,
+            stats: Arc::new(StatsCounters::default()),
         }
     }
 
@@ -153,66 +323,397 @@ where
     /// # Examples
     ///
     /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
     /// let foo_from_disk = my_data.access(|db| db.foo.clone())
     ///     .expect("Failed to access file");
+    /// # }
     /// ```
     pub fn access<F, R>(&self, action: F) -> Result<R>
     where
-        F: Fn(&T) -> R,
+        F: FnOnce(&T) -> R,
+    {
+        self.access_within(Wait::Forever, action)
+    }
+
+    /// Provide atomic read-only access to the database contents via a closure,
+    /// waiting for the lock as specified
+    pub(crate) fn access_within<F, R>(&self, wait: Wait, action: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R,
     {
-        let x = self.lock()?;
-        let y = x.deref();
-        Ok(action(y))
+        trace_span!("mvdb::access");
+        let x = self.lock_within(wait)?;
+        self.stats.reads.fetch_add(1, Ordering::Relaxed);
+        Ok(action(x.visible()))
     }
 
     /// Provide atomic writable access to the database contents via a closure.
     /// If the hash of the serialized contents after the access has changed, the database
-    /// will be written to the file.
+    /// will be written to the file. If any validator rejects the modified contents, the
+    /// changes are rolled back, nothing is written, and an Error is returned.
     ///
     /// # Examples
     ///
     /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
     /// my_data.access_mut(|db: &mut DemoData| {
     ///     db.baz = "New Value".into();
     /// }).expect("Failed to access file");
+    /// # }
     /// ```
     pub fn access_mut<F, R>(&self, action: F) -> Result<R>
     where
         F: FnOnce(&mut T) -> R,
     {
-        let mut x = self.lock()?;
-        let mut y = x.deref_mut();
-        let (_, hash_before) = hash_by_serialize(&y, self.pretty)?;
-        let ret = action(y);
-        let (ser, hash_after) = hash_by_serialize(&y, self.pretty)?;
-
-        if hash_before != hash_after {
-            just_write_string(&ser, &self.file_path)?;
-        }
+        self.access_mut_inner(Change::default(), action)
+    }
 
+    /// Provide atomic writable access to the database contents via a closure,
+    /// committing any changes with the given description
+    pub(crate) fn access_mut_inner<F, R>(&self, change: Change, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.access_mut_within(Wait::Forever, change, action)
+    }
+
+    /// Provide atomic writable access to the database contents via a closure,
+    /// waiting for the lock as specified, and committing any changes with the
+    /// given description
+    pub(crate) fn access_mut_within<F, R>(&self, wait: Wait, change: Change, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        trace_span!("mvdb::access_mut");
+        let mut x = self.lock_within(wait)?;
+        let before = self.begin_locked(&mut x)?;
+        let ret = action(&mut x.data);
+        self.commit_locked(&mut x, before, change)?;
         Ok(ret)
     }
 
-    /// Attempt to write `Self` to file
-    fn write(&self) -> Result<()> {
-        if let Ok(inner) = self.inner.lock() {
-            self.write_locked(&inner.deref())
-        } else {
-            bail!("Failed to write")
+    /// Check the contents for changes since `before` was serialized, and if
+    /// they have changed, validate and write them to storage. If validation
+    /// fails, the contents are restored to `before`. Returns whether the
+    /// contents were written.
+    ///
+    /// If the contents changed, overrides and indexes are recomputed whatever
+    /// the outcome, as the contents may have changed in memory even if they
+    /// were not written
+    pub(crate) fn commit_locked(
+        &self,
+        inner: &mut Inner<T>,
+        before: (String, u64),
+        change: Change,
+    ) -> Result<bool> {
+        let (ser_before, hash_before) = before;
+        let after = self.hash_timed(&inner.data);
+
+        let ret = match after {
+            Ok((_, hash_after)) if hash_after == hash_before => {
+                inner.snapshot = None;
+                self.stats.skipped_writes.fetch_add(1, Ordering::Relaxed);
+                trace_event!("contents unchanged, skipping write");
+                return Ok(false);
+            }
+            Ok((ser, _)) => self.commit_changes_locked(inner, ser_before, ser, change),
+            Err(e) => Err(e),
+        };
+        inner.snapshot = None;
+        inner.refresh();
+        ret
+    }
+
+    /// Validate and write changed contents, serialized as `ser`. See
+    /// `commit_locked`
+    fn commit_changes_locked(
+        &self,
+        inner: &mut Inner<T>,
+        ser_before: String,
+        ser: String,
+        change: Change,
+    ) -> Result<bool> {
+        if let Err(e) = inner.validate() {
+            Self::rollback_locked(inner, &ser_before)?;
+            return Err(e);
         }
+
+        // Both sides of the change carry any unknown fields, so that they
+        // never appear in the audit log or undo history as a difference
+        let previous = inner.with_unknown(ser_before, self.pretty)?;
+        let ser = inner.with_unknown(ser, self.pretty)?;
+        let ser = inner.with_layout(ser)?;
+        self.write_recorded_locked(inner, &previous, &ser, change)?;
+
+        if let Some(ref mut undo) = inner.undo {
+            undo.record(previous, change.label);
+        }
+        Ok(true)
+    }
+
+    /// Serialize the contents at the start of a writable access, so that
+    /// changes can be found, and keep a clone of them if `rollback_by_clone`
+    /// has been called
+    pub(crate) fn begin_locked(&self, inner: &mut Inner<T>) -> Result<(String, u64)> {
+        inner.snapshot = inner.cloner.map(|clone| clone(&inner.data));
+        self.hash_timed(&inner.data)
+    }
+
+    /// Discard any changes to the contents, restoring the clone kept by
+    /// `begin_locked` if there is one, or otherwise the previously serialized
+    /// contents. As those are deserialized, any fields that are not serialized
+    /// are then reset to their default values
+    pub(crate) fn rollback_locked(inner: &mut Inner<T>, previous: &str) -> Result<()> {
+        inner.data = match inner.snapshot.take() {
+            Some(snapshot) => snapshot,
+            None => serde_json::from_str(previous)
+                .chain_err(|| "Failed to roll back contents")?,
+        };
+        Ok(())
+    }
+
+    /// Write `contents` to storage in place of `previous`, and record them in
+    /// the history and audit log. If they cannot be recorded, the previous
+    /// contents are restored both in storage and in memory, so that no change
+    /// is persisted without being recorded. The generation only advances, and
+    /// followers are only sent the change, once it is written and recorded
+    fn write_recorded_locked(
+        &self,
+        inner: &mut Inner<T>,
+        previous: &str,
+        contents: &str,
+        change: Change,
+    ) -> Result<()> {
+        // Keep what storage held, so that the write can be undone
+        let stored = match inner.history.is_some() || inner.audit.is_some() {
+            true => Some(self.storage.read_all()?),
+            false => None,
+        };
+        self.write_timed(contents)?;
+
+        let generation = inner.generation + 1;
+        if let Err(e) = self.record_locked(inner, generation, previous, contents, change) {
+            if let Some(stored) = stored {
+                self.storage.write_all(&stored)
+                    .chain_err(|| "Failed to restore contents after a change could not be recorded")?;
+                if inner.layout.is_some() {
+                    inner.layout = Some(String::from_utf8_lossy(&stored).into_owned());
+                }
+            }
+            Self::rollback_locked(inner, previous)?;
+            return Err(e);
+        }
+
+        inner.generation = generation;
+        inner.publish(contents);
+        Ok(())
+    }
+
+    /// Record a change in the history and audit log, if enabled. If either
+    /// fails, the change is removed from the history again
+    fn record_locked(
+        &self,
+        inner: &mut Inner<T>,
+        generation: u64,
+        previous: &str,
+        contents: &str,
+        change: Change,
+    ) -> Result<()> {
+        let mut recorded = match inner.history {
+            Some(ref history) => history.record(generation, contents),
+            None => Ok(()),
+        };
+        if recorded.is_ok() {
+            if let Some(ref mut audit) = inner.audit {
+                let actor = change.actor.or(self.actor.as_deref());
+                recorded = audit.record(generation, actor, change.reason, previous, contents);
+            }
+        }
+
+        if recorded.is_err() {
+            if let Some(ref history) = inner.history {
+                history.discard(generation)?;
+            }
+        }
+        recorded
+    }
+
+    /// Replace the contents with previously serialized contents, validate
+    /// them, and write them to storage. If validation fails, the contents
+    /// are left unchanged. Returns the serialized contents that were replaced
+    pub(crate) fn restore_locked(
+        &self,
+        inner: &mut Inner<T>,
+        contents: &str,
+        change: Change,
+    ) -> Result<String> {
+        let ret = self.restore_changes_locked(inner, contents, change);
+        inner.snapshot = None;
+        inner.refresh();
+        ret
+    }
+
+    /// Replace, validate and write the contents. See `restore_locked`
+    fn restore_changes_locked(
+        &self,
+        inner: &mut Inner<T>,
+        contents: &str,
+        change: Change,
+    ) -> Result<String> {
+        let (previous, _) = self.begin_locked(inner)?;
+        inner.data = serde_json::from_str(contents)
+            .chain_err(|| "Deserialize error")?;
+
+        if let Err(e) = inner.validate() {
+            Self::rollback_locked(inner, &previous)?;
+            return Err(e);
+        }
+
+        let previous = inner.with_unknown(previous, self.pretty)?;
+        let contents = inner.with_unknown(contents.into(), self.pretty)?;
+        let contents = inner.with_layout(contents)?;
+        self.write_recorded_locked(inner, &previous, &contents, change)?;
+        Ok(previous)
     }
 
-    /// Raw write to file without locks
+    /// Provide atomic writable access to the serialized form of the database
+    /// contents. The modified document must still deserialize into a `T`,
+    /// otherwise an Error is returned and the contents are left unchanged
+    pub(crate) fn access_value_mut<F>(&self, action: F) -> Result<()>
+    where
+        F: FnOnce(&mut Value) -> Result<()>,
+    {
+        self.access_mut(|t| {
+            let mut doc = serde_json::to_value(&*t)
+                .chain_err(|| "Failed to serialize")?;
+            action(&mut doc)?;
+            *t = serde_json::from_value(doc)
+                .chain_err(|| "Modified contents do not match schema")?;
+            Ok(())
+        })?
+    }
+
+    /// The generation of the database contents, which increases by one each
+    /// time modified contents are written to storage
+    pub fn generation(&self) -> Result<u64> {
+        Ok(self.lock()?.generation)
+    }
+
+    /// Obtain a snapshot of the counters kept for this database, which are
+    /// shared by all handles to it
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// my_data.access_mut(|db| db.foo = "bar".into()).expect("Failed to access file");
+    /// my_data.access_mut(|db| db.foo = "bar".into()).expect("Failed to access file");
+    ///
+    /// let stats = my_data.stats();
+    /// assert_eq!(stats.skipped_writes, 1);
+    /// # }
+    /// ```
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    /// Serialize and hash the contents, recording the time taken
+    pub(crate) fn hash_timed(&self, data: &T) -> Result<(String, u64)> {
+        let start = Instant::now();
+        let ret = hash_by_serialize(data, self.pretty);
+        self.stats.serialize.record_since(start);
+        ret
+    }
+
+    /// Write serialized contents to storage, recording the time taken
+    fn write_timed(&self, contents: &str) -> Result<()> {
+        let start = Instant::now();
+        self.storage.write_all(contents.as_bytes())?;
+        let _elapsed = self.stats.write.record_since(start);
+
+        self.stats.writes.fetch_add(1, Ordering::Relaxed);
+        self.stats.bytes_written.fetch_add(contents.len() as u64, Ordering::Relaxed);
+        trace_event!(bytes = contents.len(), elapsed_us = _elapsed.as_micros() as u64, "contents written");
+        Ok(())
+    }
+
+    /// Attempt to write `Self` to storage
+    fn flush(&self) -> Result<()> {
+        let inner = self.lock()?;
+        self.write_locked(&inner.data)
+    }
+
+    /// Raw write to storage without locks
     fn write_locked(&self, inner: &T) -> Result<()> {
-        just_write(&inner.deref(), &self.file_path, self.pretty)
+        self.write_timed(&serialize(inner, self.pretty)?)
     }
 
-    /// Return the MutexGuard for `Mvdb`
-    fn lock(&self) -> Result<MutexGuard<T>> {
-        match self.inner.lock() {
-            Err(_) => bail!("failed to lock"),
-            Ok(lock) => Ok(lock),
+    /// Return the lock guard for `Mvdb`
+    pub(crate) fn lock(&self) -> Result<LockGuard<'_, T>> {
+        self.lock_within(Wait::Forever)
+    }
+
+    /// Return the lock guard for `Mvdb`, waiting for it as specified. If the
+    /// current thread already holds the lock, an `ErrorKind::Reentrant` is
+    /// returned rather than deadlocking
+    pub(crate) fn lock_within(&self, wait: Wait) -> Result<LockGuard<'_, T>> {
+        let key = &*self.inner as *const Mutex<Inner<T>> as usize;
+        if held_by_current_thread(key) {
+            bail!(ErrorKind::Reentrant);
         }
+
+        let start = Instant::now();
+        let mut attempt = 0;
+        let lock = loop {
+            match self.inner.try_lock() {
+                Ok(lock) => break lock,
+                Err(TryLockError::Poisoned(_)) => bail!("failed to lock"),
+                Err(TryLockError::WouldBlock) => {}
+            }
+
+            if attempt == 0 {
+                self.stats.lock_contended.fetch_add(1, Ordering::Relaxed);
+            }
+
+            match wait {
+                Wait::Forever => match self.inner.lock() {
+                    Err(_) => bail!("failed to lock"),
+                    Ok(lock) => break lock,
+                },
+                Wait::Never => bail!(ErrorKind::WouldBlock),
+                Wait::Until(deadline) => {
+                    if !backoff(attempt, deadline) {
+                        bail!(ErrorKind::WouldBlock);
+                    }
+                }
+            }
+            attempt += 1;
+        };
+
+        let _waited = self.stats.lock_wait.record_since(start);
+        trace_event!(wait_us = _waited.as_micros() as u64, "lock acquired");
+        Ok(LockGuard::new(lock, key))
     }
 }
 
@@ -226,13 +727,21 @@ where
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
     /// let file = Path::new("demo.json");
-    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_or_default(&file);
+    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_or_default(&file)
     ///     .expect("Could not write to file");
+    /// # }
     /// ```
     pub fn from_file_or_default(path: &Path) -> Result<Self> {
-        Self::from_file_or_default_inner(path, false)
+        Self::from_storage_or_default_inner(Arc::new(FileStorage::new(path)), false)
     }
 
     /// Attempt to load from a file. If the file does not exist,
@@ -242,22 +751,181 @@ where
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
     /// let file = Path::new("demo_pretty.json");
-    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_or_default_pretty(&file);
+    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_or_default_pretty(&file)
     ///     .expect("Could not write to file");
+    /// # }
     /// ```
     pub fn from_file_or_default_pretty(path: &Path) -> Result<Self> {
-        Self::from_file_or_default_inner(path, true)
+        Self::from_storage_or_default_inner(Arc::new(FileStorage::new(path)), true)
     }
 
-    /// Attempt to load from a file. If the file does not exist,
-    /// or if the schema does not match, a new file will be written
-    /// with the default contents of `T`.
-    fn from_file_or_default_inner(path: &Path, pretty: bool) -> Result<Self> {
-        match just_load(path) {
-            Ok(data) => Ok(Self::new_no_write(data, path, pretty)),
-            Err(_) => Self::new_inner(T::default(), path, pretty),
+    /// Attempt to load from a `Storage`. If the storage is empty,
+    /// or if the schema does not match, the default contents of `T`
+    /// will be written to the storage.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// let my_data: Mvdb<DemoData> = Mvdb::with_storage_or_default(MemoryStorage::new())
+    ///     .expect("Could not write to storage");
+    /// # }
+    /// ```
+    pub fn with_storage_or_default<S>(storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::from_storage_or_default_inner(Arc::new(storage), false)
+    }
+
+    /// Attempt to load from a `Storage`. If the storage is empty,
+    /// or if the schema does not match, the default contents of `T`
+    /// will be written to the storage. Any writes made will use
+    /// pretty-printed JSON
+    pub fn with_storage_or_default_pretty<S>(storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::from_storage_or_default_inner(Arc::new(storage), true)
+    }
+
+    /// Attempt to load from a storage. If the storage is empty,
+    /// or if the schema does not match, the default contents of `T`
+    /// will be written to the storage.
+    fn from_storage_or_default_inner(storage: Arc<dyn Storage>, pretty: bool) -> Result<Self> {
+        match just_load_from(&*storage) {
+            Ok(data) => Ok(Self::new_no_write(data, storage, pretty)),
+            Err(_) => Self::new_inner(T::default(), storage, pretty),
         }
     }
-}
\ No newline at end of file
+
+    /// Attempt to load from a file, filling in any fields missing from it
+    /// with those of the default contents of `T`. This allows fields to be
+    /// added to `T` without marking each one `#[serde(default)]`. If any
+    /// fields were filled in, the completed contents are written back to the
+    /// file, along with any fields in the file that `T` does not know. Those
+    /// fields are only kept in this first write, unless
+    /// `preserve_unknown_fields` is also called. If the file does not exist,
+    /// a new file will be written with the default contents of `T`. Unlike
+    /// `from_file_or_default`, if the contents still do not match the
+    /// schema, an Error is returned, and the file is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::Mvdb;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// let file = Path::new("demo.json");
+    /// let my_data: Mvdb<DemoData> = Mvdb::from_file_with_defaults(&file)
+    ///     .expect("Could not load file");
+    /// # }
+    /// ```
+    pub fn from_file_with_defaults(path: &Path) -> Result<Self> {
+        Self::from_storage_with_defaults_inner(Arc::new(FileStorage::new(path)), false)
+    }
+
+    /// Attempt to load from a file, filling in any fields missing from it
+    /// with those of the default contents of `T`, as `from_file_with_defaults`
+    /// does. Any writes made will use pretty-printed JSON
+    pub fn from_file_with_defaults_pretty(path: &Path) -> Result<Self> {
+        Self::from_storage_with_defaults_inner(Arc::new(FileStorage::new(path)), true)
+    }
+
+    /// Attempt to load from a `Storage`, filling in any fields missing from
+    /// it with those of the default contents of `T`, as `from_file_with_defaults`
+    /// does
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Config { name: String, retries: u32 }
+    ///
+    /// impl Default for Config {
+    ///     fn default() -> Self {
+    ///         Config { name: "demo".into(), retries: 3 }
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// // Written before `retries` was added, by a program that also knows `extra`
+    /// let storage = MemoryStorage::with_contents(br#"{"name":"old","extra":{"k":1}}"#);
+    ///
+    /// let my_data: Mvdb<Config> = Mvdb::with_storage_with_defaults(storage.clone())
+    ///     .expect("Could not load storage");
+    ///
+    /// assert_eq!(my_data.access(|cfg| cfg.retries).unwrap(), 3);
+    ///
+    /// let written: serde_json::Value = serde_json::from_slice(&storage.contents().unwrap()).unwrap();
+    /// assert_eq!(written["retries"], 3);
+    /// assert_eq!(written["extra"]["k"], 1);
+    /// # }
+    /// ```
+    pub fn with_storage_with_defaults<S>(storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::from_storage_with_defaults_inner(Arc::new(storage), false)
+    }
+
+    /// Attempt to load from a `Storage`, filling in any fields missing from
+    /// it with those of the default contents of `T`, as `from_file_with_defaults`
+    /// does. Any writes made will use pretty-printed JSON
+    pub fn with_storage_with_defaults_pretty<S>(storage: S) -> Result<Self>
+    where
+        S: Storage + 'static,
+    {
+        Self::from_storage_with_defaults_inner(Arc::new(storage), true)
+    }
+
+    /// Attempt to load from a storage, filling in any missing fields with
+    /// those of the default contents of `T`, and writing back the completed
+    /// contents if any were missing
+    fn from_storage_with_defaults_inner(storage: Arc<dyn Storage>, pretty: bool) -> Result<Self> {
+        if !storage.exists()? {
+            return Self::new_inner(T::default(), storage, pretty);
+        }
+
+        let stored: Value = just_load_from(&*storage)?;
+        let defaults = serde_json::to_value(T::default())
+            .chain_err(|| "Failed to serialize")?;
+        let mut doc = stored.clone();
+        fill_defaults(&mut doc, defaults);
+
+        let data = serde_json::from_value(doc.clone())
+            .chain_err(|| "Deserialize error")?;
+        let new_self = Self::new_no_write(data, storage, pretty);
+
+        // Write back the completed document, rather than the contents, so that
+        // any fields `T` does not know are kept
+        if doc != stored {
+            new_self.write_timed(&serialize(&doc, pretty)?)?;
+        }
+        Ok(new_self)
+    }
+}
@@ -0,0 +1,440 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Support for [JSON Patch](https://tools.ietf.org/html/rfc6902) and
+//! [JSON Merge Patch](https://tools.ietf.org/html/rfc7396) documents
+//!
+//! Patches are applied to the serialized form of the database contents, which
+//! must still deserialize into the stored type afterwards. The functions in this
+//! module operate on plain `serde_json::Value` documents, and are used by the
+//! `apply_patch`, `apply_merge_patch` and `diff` methods of `Mvdb`.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::{Map, Value};
+
+use errors::*;
+use mvdb::Mvdb;
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Apply a [JSON Patch](https://tools.ietf.org/html/rfc6902) to the database
+    /// contents. Either every operation is applied, or none are. The patched
+    /// document must still deserialize into a `T`. If the contents changed,
+    /// the database will be written to the file
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { name: String, tags: Vec<String> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// my_data.apply_patch(&json!([
+    ///     { "op": "replace", "path": "/name", "value": "primary" },
+    ///     { "op": "add", "path": "/tags/-", "value": "blue" },
+    /// ])).expect("Failed to apply patch");
+    ///
+    /// assert_eq!(my_data.access(|db| db.tags.clone()).unwrap(), vec!["blue"]);
+    /// # }
+    /// ```
+    ///
+    /// If any operation fails, or the patched document is no longer a `T`,
+    /// neither the contents nor the storage change:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { name: String, tags: Vec<String> }
+    /// # fn main() {
+    /// let storage = MemoryStorage::new();
+    /// let my_data = Mvdb::new_with_storage(Config::default(), storage.clone()).unwrap();
+    /// let before = storage.contents().unwrap();
+    ///
+    /// let result = my_data.apply_patch(&json!([
+    ///     { "op": "replace", "path": "/name", "value": "primary" },
+    ///     { "op": "test", "path": "/tags", "value": ["blue"] },
+    /// ]));
+    /// assert!(result.is_err());
+    ///
+    /// let result = my_data.apply_patch(&json!([
+    ///     { "op": "replace", "path": "/name", "value": "primary" },
+    ///     { "op": "remove", "path": "/tags" },
+    /// ]));
+    /// assert!(result.is_err());
+    ///
+    /// assert_eq!(my_data.access(|db| db.name.clone()).unwrap(), "");
+    /// assert_eq!(storage.contents().unwrap(), before);
+    /// # }
+    /// ```
+    pub fn apply_patch(&self, patch: &Value) -> Result<()> {
+        self.access_value_mut(|doc| apply_patch(doc, patch))
+    }
+
+    /// Apply a [JSON Merge Patch](https://tools.ietf.org/html/rfc7396) to the
+    /// database contents. The patched document must still deserialize into a
+    /// `T`. If the contents changed, the database will be written to the file
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { name: String, tags: Vec<String> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// my_data.apply_merge_patch(&json!({ "name": "primary" }))
+    ///     .expect("Failed to apply patch");
+    /// # }
+    /// ```
+    pub fn apply_merge_patch(&self, patch: &Value) -> Result<()> {
+        self.access_value_mut(|doc| {
+            merge_patch(doc, patch);
+            Ok(())
+        })
+    }
+
+    /// Produce a [JSON Patch](https://tools.ietf.org/html/rfc6902) that would
+    /// transform the current database contents into `other`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { name: String, tags: Vec<String> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// let other = Config { name: "primary".into(), tags: vec![] };
+    /// let patch = my_data.diff(&other).expect("Failed to serialize");
+    /// assert_eq!(patch, json!([{ "op": "replace", "path": "/name", "value": "primary" }]));
+    /// # }
+    /// ```
+    pub fn diff(&self, other: &T) -> Result<Value> {
+        let before = self.access(|t| serde_json::to_value(t))?
+            .chain_err(|| "Failed to serialize")?;
+        let after = serde_json::to_value(other)
+            .chain_err(|| "Failed to serialize")?;
+        Ok(diff(&before, &after))
+    }
+}
+
+/// Apply a [JSON Patch](https://tools.ietf.org/html/rfc6902) to a document
+///
+/// Either every operation is applied, or none are, and an Error describing
+/// the first operation that could not be applied is returned.
+///
+/// # Examples
+///
+/// Array indices must be written without leading zeros:
+///
+/// ```rust
+/// # #[macro_use] extern crate serde_json;
+/// # extern crate mvdb;
+/// # use mvdb::patch::apply_patch;
+/// # fn main() {
+/// let mut doc = json!({ "l": [1, 2, 3] });
+/// assert!(apply_patch(&mut doc, &json!([{ "op": "remove", "path": "/l/01" }])).is_err());
+/// assert!(apply_patch(&mut doc, &json!([{ "op": "add", "path": "/l/+1", "value": 4 }])).is_err());
+///
+/// apply_patch(&mut doc, &json!([{ "op": "remove", "path": "/l/1" }])).unwrap();
+/// assert_eq!(doc, json!({ "l": [1, 3] }));
+/// # }
+/// ```
+///
+/// Values can be moved and copied between paths, and a `test` operation
+/// which does not match rejects the whole patch:
+///
+/// ```rust
+/// # #[macro_use] extern crate serde_json;
+/// # extern crate mvdb;
+/// # use mvdb::patch::apply_patch;
+/// # fn main() {
+/// let mut doc = json!({ "a": { "x": 1 }, "b": {}, "l": [1, 2] });
+/// apply_patch(&mut doc, &json!([
+///     { "op": "move", "from": "/a/x", "path": "/b/y" },
+///     { "op": "copy", "from": "/b/y", "path": "/l/0" },
+///     { "op": "remove", "path": "/a" },
+///     { "op": "test", "path": "/l", "value": [1, 1, 2] },
+/// ])).unwrap();
+/// assert_eq!(doc, json!({ "b": { "y": 1 }, "l": [1, 1, 2] }));
+///
+/// // A value cannot be moved into itself
+/// let moved = apply_patch(&mut doc, &json!([{ "op": "move", "from": "/b", "path": "/b/z" }]));
+/// assert!(moved.is_err());
+///
+/// // Nothing is applied if any operation fails
+/// let failed = apply_patch(&mut doc, &json!([
+///     { "op": "remove", "path": "/b" },
+///     { "op": "test", "path": "/l/0", "value": 2 },
+/// ]));
+/// assert!(failed.is_err());
+/// assert_eq!(doc, json!({ "b": { "y": 1 }, "l": [1, 1, 2] }));
+///
+/// // Removing or copying a missing path fails
+/// assert!(apply_patch(&mut doc, &json!([{ "op": "remove", "path": "/c" }])).is_err());
+/// assert!(apply_patch(&mut doc, &json!([{ "op": "copy", "from": "/c", "path": "/d" }])).is_err());
+/// # }
+/// ```
+pub fn apply_patch(doc: &mut Value, patch: &Value) -> Result<()> {
+    let ops = match *patch {
+        Value::Array(ref ops) => ops,
+        _ => bail!(conflict("patch must be an array of operations")),
+    };
+
+    let mut patched = doc.clone();
+    for op in ops {
+        apply_op(&mut patched, op)?;
+    }
+    *doc = patched;
+    Ok(())
+}
+
+/// Apply a [JSON Merge Patch](https://tools.ietf.org/html/rfc7396) to a document
+pub fn merge_patch(doc: &mut Value, patch: &Value) {
+    let patch = match *patch {
+        Value::Object(ref patch) => patch,
+        _ => {
+            *doc = patch.clone();
+            return;
+        }
+    };
+
+    if !doc.is_object() {
+        *doc = Value::Object(Map::new());
+    }
+
+    if let Value::Object(ref mut map) = *doc {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(key);
+            } else {
+                merge_patch(map.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Produce a [JSON Patch](https://tools.ietf.org/html/rfc6902) that would
+/// transform `before` into `after`
+pub fn diff(before: &Value, after: &Value) -> Value {
+    let mut ops = Vec::new();
+    diff_into(&mut ops, String::new(), before, after);
+    Value::Array(ops)
+}
+
+fn diff_into(ops: &mut Vec<Value>, path: String, before: &Value, after: &Value) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (key, value) in before {
+                let child = format!("{}/{}", path, escape(key));
+                match after.get(key) {
+                    Some(other) => diff_into(ops, child, value, other),
+                    None => ops.push(json!({ "op": "remove", "path": child })),
+                }
+            }
+            for (key, value) in after {
+                if !before.contains_key(key) {
+                    let child = format!("{}/{}", path, escape(key));
+                    ops.push(json!({ "op": "add", "path": child, "value": value }));
+                }
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            for (i, (value, other)) in before.iter().zip(after.iter()).enumerate() {
+                diff_into(ops, format!("{}/{}", path, i), value, other);
+            }
+            for i in (after.len()..before.len()).rev() {
+                ops.push(json!({ "op": "remove", "path": format!("{}/{}", path, i) }));
+            }
+            for (i, value) in after.iter().enumerate().skip(before.len()) {
+                let child = format!("{}/{}", path, i);
+                ops.push(json!({ "op": "add", "path": child, "value": value }));
+            }
+        }
+        _ => {
+            if before != after {
+                ops.push(json!({ "op": "replace", "path": path, "value": after }));
+            }
+        }
+    }
+}
+
+/// Apply a single JSON Patch operation
+fn apply_op(doc: &mut Value, op: &Value) -> Result<()> {
+    let name = field(op, "op")?;
+    let path = field(op, "path")?;
+
+    match name {
+        "add" => add(doc, path, value(op)?.clone()),
+        "remove" => remove(doc, path).map(|_| ()),
+        "replace" => {
+            let value = value(op)?.clone();
+            match doc.pointer_mut(path) {
+                Some(slot) => {
+                    *slot = value;
+                    Ok(())
+                }
+                None => bail!(ErrorKind::PathNotFound(path.into())),
+            }
+        }
+        "move" => {
+            let from = field(op, "from")?;
+            if path.starts_with(from) && path[from.len()..].starts_with('/') {
+                bail!(conflict(&format!("cannot move {} into itself", from)));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        "copy" => {
+            let from = field(op, "from")?;
+            let value = match doc.pointer(from) {
+                Some(value) => value.clone(),
+                None => bail!(ErrorKind::PathNotFound(from.into())),
+            };
+            add(doc, path, value)
+        }
+        "test" => match doc.pointer(path) {
+            Some(current) if current == value(op)? => Ok(()),
+            Some(_) => bail!(conflict(&format!("test failed at {}", path))),
+            None => bail!(ErrorKind::PathNotFound(path.into())),
+        },
+        other => bail!(conflict(&format!("unknown operation {:?}", other))),
+    }
+}
+
+/// Add a value at a path, as described by RFC 6902
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<()> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    let (parent, key) = split(path)?;
+    match doc.pointer_mut(parent) {
+        Some(&mut Value::Object(ref mut map)) => {
+            map.insert(key, value);
+        }
+        Some(&mut Value::Array(ref mut array)) => {
+            if key == "-" {
+                array.push(value);
+            } else {
+                match index(&key) {
+                    Some(i) if i <= array.len() => array.insert(i, value),
+                    _ => bail!(ErrorKind::PathNotFound(path.into())),
+                }
+            }
+        }
+        _ => bail!(ErrorKind::PathNotFound(path.into())),
+    }
+    Ok(())
+}
+
+/// Remove and return the value at a path, as described by RFC 6902
+fn remove(doc: &mut Value, path: &str) -> Result<Value> {
+    if path.is_empty() {
+        bail!(conflict("cannot remove the whole document"));
+    }
+
+    let (parent, key) = split(path)?;
+    let removed = match doc.pointer_mut(parent) {
+        Some(&mut Value::Object(ref mut map)) => map.remove(&key),
+        Some(&mut Value::Array(ref mut array)) => match index(&key) {
+            Some(i) if i < array.len() => Some(array.remove(i)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    match removed {
+        Some(value) => Ok(value),
+        None => bail!(ErrorKind::PathNotFound(path.into())),
+    }
+}
+
+/// Split a path into the path of its parent, and its unescaped last segment
+fn split(path: &str) -> Result<(&str, String)> {
+    match path.rfind('/') {
+        Some(i) => Ok((&path[..i], unescape(&path[i + 1..]))),
+        None => bail!(ErrorKind::PathNotFound(path.into())),
+    }
+}
+
+/// Parse an array index, which RFC 6901 requires to be written without
+/// leading zeros or a sign
+fn index(key: &str) -> Option<usize> {
+    if key.is_empty() || !key.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if key.len() > 1 && key.starts_with('0') {
+        return None;
+    }
+    key.parse().ok()
+}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(key: &str) -> String {
+    key.replace("~1", "/").replace("~0", "~")
+}
+
+/// Obtain a string field of a patch operation
+fn field<'a>(op: &'a Value, name: &str) -> Result<&'a str> {
+    match op.get(name).and_then(|f| f.as_str()) {
+        Some(f) => Ok(f),
+        None => bail!(conflict(&format!("operation is missing {:?}", name))),
+    }
+}
+
+/// Obtain the value of a patch operation
+fn value(op: &Value) -> Result<&Value> {
+    match op.get("value") {
+        Some(v) => Ok(v),
+        None => bail!(conflict("operation is missing \"value\"")),
+    }
+}
+
+fn conflict(reason: &str) -> ErrorKind {
+    ErrorKind::PatchConflict(reason.into())
+}
@@ -0,0 +1,111 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+
+use errors::*;
+use helpers::{check_pointer, set_pointer};
+use mvdb::Mvdb;
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Obtain the value at a [JSON Pointer](https://tools.ietf.org/html/rfc6901)
+    /// path within the serialized database contents, without needing the
+    /// concrete type of the contents
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # extern crate serde_json;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Network { port: u16 }
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { network: Network }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// let port = my_data.get_path("/network/port")
+    ///     .expect("No value at path");
+    /// assert_eq!(port, serde_json::json!(0));
+    /// # }
+    /// ```
+    pub fn get_path(&self, pointer: &str) -> Result<Value> {
+        let doc = self.access(|t| serde_json::to_value(t))?
+            .chain_err(|| "Failed to serialize")?;
+        match doc.pointer(pointer) {
+            Some(value) => Ok(value.clone()),
+            None => bail!(ErrorKind::PathNotFound(pointer.into())),
+        }
+    }
+
+    /// Replace the value at a [JSON Pointer](https://tools.ietf.org/html/rfc6901)
+    /// path within the serialized database contents. The modified document must
+    /// still deserialize into a `T`, and the path must name a field of `T`,
+    /// otherwise an Error is returned and the contents are left unchanged. If
+    /// the contents changed, the database will be written to the file
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # extern crate serde_json;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Network { port: u16 }
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { network: Network }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// my_data.set_path("/network/port", serde_json::json!(8080))
+    ///     .expect("Failed to set value");
+    ///
+    /// // Values that do not match the schema are rejected
+    /// assert!(my_data.set_path("/network/port", serde_json::json!("eighty")).is_err());
+    ///
+    /// // As are paths that do not name a field
+    /// assert!(my_data.set_path("/network/prot", serde_json::json!(8081)).is_err());
+    /// # }
+    /// ```
+    pub fn set_path(&self, pointer: &str, value: Value) -> Result<()> {
+        self.access_mut(|t| {
+            let mut doc = serde_json::to_value(&*t)
+                .chain_err(|| "Failed to serialize")?;
+            set_pointer(&mut doc, pointer, value.clone())?;
+            let updated = serde_json::from_value(doc)
+                .chain_err(|| "Modified contents do not match schema")?;
+
+            check_pointer(&updated, pointer, &value)?;
+            *t = updated;
+            Ok(())
+        })?
+    }
+}
@@ -0,0 +1,294 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::cmp::Ordering;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use errors::*;
+use mvdb::Mvdb;
+
+/// An item borrowed from a collection selected by a `Query`
+type Item<'x, C> = <&'x C as IntoIterator>::Item;
+
+type Predicate<'a, C> = Box<dyn for<'x> Fn(&Item<'x, C>) -> bool + 'a>;
+type KeyFn<'a, C, K> = Box<dyn for<'x> Fn(&Item<'x, C>) -> K + 'a>;
+
+/// An item of a collection that can be copied out of a `Query`
+///
+/// This is implemented for the items of collections such as `Vec` (`&I`), and
+/// of maps such as `BTreeMap` and `HashMap` (`(&K, &V)`)
+pub trait QueryItem {
+    /// The owned form of the item
+    type Owned;
+
+    /// Copy the item out of the collection
+    fn to_owned_item(self) -> Self::Owned;
+}
+
+impl<I: Clone> QueryItem for &I {
+    type Owned = I;
+
+    fn to_owned_item(self) -> I {
+        self.clone()
+    }
+}
+
+impl<K: Clone, V: Clone> QueryItem for (&K, &V) {
+    type Owned = (K, V);
+
+    fn to_owned_item(self) -> (K, V) {
+        (self.0.clone(), self.1.clone())
+    }
+}
+
+/// One page of results from a `Query`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<O> {
+    /// The items on this page
+    pub items: Vec<O>,
+
+    /// A cursor to pass to `Query::after` to obtain the next page, if the
+    /// query is ordered and more items remain
+    pub next: Option<String>,
+}
+
+/// A query over a collection within the contents of an `Mvdb`, created by
+/// `Mvdb::query`. The query is not run until `run` or `page` is called
+pub struct Query<'a, T: 'a, C: ?Sized + 'a, K = ()>
+where
+    for<'x> &'x C: IntoIterator,
+{
+    db: &'a Mvdb<T>,
+    collection: Box<dyn Fn(&T) -> &C + 'a>,
+    filters: Vec<Predicate<'a, C>>,
+    key: Option<KeyFn<'a, C, K>>,
+    descending: bool,
+    offset: usize,
+    limit: Option<usize>,
+    after: Option<String>,
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Start a query over a collection within the database contents, such as
+    /// a `Vec` or a map, selected by the given closure
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # use std::collections::BTreeMap;
+    /// #[derive(Serialize, Deserialize, Clone, Default)]
+    /// struct Record { name: String, team: String }
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Directory { records: Vec<Record>, teams: BTreeMap<String, u32> }
+    ///
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Directory::default(), MemoryStorage::new()).unwrap();
+    /// # my_data.access_mut(|d| {
+    /// #     for &(name, team) in &[("carol", "ops"), ("alice", "ops"), ("bob", "dev"), ("dave", "ops")] {
+    /// #         d.records.push(Record { name: name.into(), team: team.into() });
+    /// #         *d.teams.entry(team.into()).or_insert(0) += 1;
+    /// #     }
+    /// # }).unwrap();
+    /// let first = my_data.query(|d| &d.records)
+    ///     .filter(|r| r.team == "ops")
+    ///     .order_by(|r| r.name.clone())
+    ///     .limit(2)
+    ///     .page()
+    ///     .expect("Failed to access file");
+    /// assert_eq!(first.items[0].name, "alice");
+    ///
+    /// let rest = my_data.query(|d| &d.records)
+    ///     .filter(|r| r.team == "ops")
+    ///     .order_by(|r| r.name.clone())
+    ///     .after(&first.next.unwrap())
+    ///     .run()
+    ///     .expect("Failed to access file");
+    /// assert_eq!(rest[0].name, "dave");
+    ///
+    /// let teams = my_data.query(|d| &d.teams)
+    ///     .order_by_desc(|&(_, count)| *count)
+    ///     .run()
+    ///     .expect("Failed to access file");
+    /// assert_eq!(teams[0], ("ops".to_string(), 3));
+    /// # }
+    /// ```
+    pub fn query<'a, C, F>(&'a self, collection: F) -> Query<'a, T, C>
+    where
+        C: ?Sized + 'a,
+        for<'x> &'x C: IntoIterator,
+        F: Fn(&T) -> &C + 'a,
+    {
+        Query {
+            db: self,
+            collection: Box::new(collection),
+            filters: Vec::new(),
+            key: None,
+            descending: false,
+            offset: 0,
+            limit: None,
+            after: None,
+        }
+    }
+}
+
+impl<'a, T, C, K> Query<'a, T, C, K>
+where
+    T: Serialize + DeserializeOwned,
+    C: ?Sized + 'a,
+    for<'x> &'x C: IntoIterator,
+    K: Ord + Serialize + DeserializeOwned,
+{
+    /// Only include items for which the predicate returns `true`. Each
+    /// call adds another predicate that must also hold
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: for<'x> Fn(&Item<'x, C>) -> bool + 'a,
+    {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    /// Order the items by the given key, smallest first. Items with equal
+    /// keys retain the order of the collection
+    pub fn order_by<J, F>(self, key: F) -> Query<'a, T, C, J>
+    where
+        F: for<'x> Fn(&Item<'x, C>) -> J + 'a,
+    {
+        Query {
+            db: self.db,
+            collection: self.collection,
+            filters: self.filters,
+            key: Some(Box::new(key)),
+            descending: false,
+            offset: self.offset,
+            limit: self.limit,
+            after: self.after,
+        }
+    }
+
+    /// Order the items by the given key, largest first
+    pub fn order_by_desc<J, F>(self, key: F) -> Query<'a, T, C, J>
+    where
+        F: for<'x> Fn(&Item<'x, C>) -> J + 'a,
+    {
+        Query {
+            descending: true,
+            ..self.order_by(key)
+        }
+    }
+
+    /// Skip the given number of items
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Include at most the given number of items
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Only include items that come after the given cursor, as returned in
+    /// `Page::next` by a previous query with the same ordering. The cursor
+    /// is the key of the last item on that page, so the ordering key should
+    /// be unique for pages to neither skip nor repeat items
+    pub fn after(mut self, cursor: &str) -> Self {
+        self.after = Some(cursor.into());
+        self
+    }
+
+    /// Run the query, returning a copy of every matching item
+    pub fn run<O>(self) -> Result<Vec<O>>
+    where
+        for<'x> Item<'x, C>: QueryItem<Owned = O>,
+    {
+        Ok(self.page()?.items)
+    }
+
+    /// Run the query, returning a copy of every matching item along with a
+    /// cursor for the next page of results
+    pub fn page<O>(self) -> Result<Page<O>>
+    where
+        for<'x> Item<'x, C>: QueryItem<Owned = O>,
+    {
+        let after = match (self.after.as_ref(), self.key.is_some()) {
+            (None, _) => None,
+            (Some(cursor), true) => {
+                Some(serde_json::from_str::<K>(cursor).chain_err(|| "Invalid cursor")?)
+            }
+            (Some(_), false) => bail!("Cursors require the query to be ordered"),
+        };
+
+        let query = &self;
+        self.db.access(move |data| {
+            let mut found: Vec<(Option<K>, Item<C>)> = (query.collection)(data)
+                .into_iter()
+                .filter(|item| query.filters.iter().all(|f| f(item)))
+                .map(|item| (query.key.as_ref().map(|key| key(&item)), item))
+                .collect();
+
+            let direction = |a: &K, b: &K| match query.descending {
+                false => a.cmp(b),
+                true => b.cmp(a),
+            };
+
+            if query.key.is_some() {
+                found.sort_by(|a, b| match (&a.0, &b.0) {
+                    (Some(a), Some(b)) => direction(a, b),
+                    _ => Ordering::Equal,
+                });
+            }
+
+            if let Some(ref after) = after {
+                found.retain(|item| match item.0 {
+                    Some(ref key) => direction(key, after) == Ordering::Greater,
+                    None => false,
+                });
+            }
+
+            let mut found = found.into_iter().skip(query.offset);
+            let page: Vec<_> = found.by_ref().take(query.limit.unwrap_or(usize::MAX)).collect();
+
+            let next = match (found.next(), page.last()) {
+                (Some(_), Some(&(Some(ref last), _))) => {
+                    Some(serde_json::to_string(last).chain_err(|| "Failed to serialize cursor")?)
+                }
+                _ => None,
+            };
+            let items = page.into_iter().map(|(_, item)| item.to_owned_item()).collect();
+
+            Ok(Page { items, next })
+        })?
+    }
+}
@@ -0,0 +1,147 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::Mvdb;
+use storage::{FileStorage, Storage};
+
+/// A read-only handle to an `Mvdb`
+///
+/// An `MvdbReader` shares its contents with the `Mvdb` it was created from,
+/// and will observe any changes made through that `Mvdb`, but provides no way
+/// to modify the contents or write to storage. This makes it suitable for
+/// handing to plugins or worker threads that should never cause a write.
+pub struct MvdbReader<T> {
+    db: Mvdb<T>,
+}
+
+/// Implement `Clone` manually, otherwise Rust expects `T` to also impl `Clone`,
+/// which is not necessary
+impl<T> Clone for MvdbReader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Obtain a read-only handle to the same contents as this `Mvdb`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let reader = my_data.read_only();
+    ///
+    /// my_data.access_mut(|db| db.foo = "New Value".into())
+    ///     .expect("Failed to access file");
+    ///
+    /// let foo = reader.access(|db| db.foo.clone())
+    ///     .expect("Failed to access file");
+    /// assert_eq!(foo, "New Value");
+    /// # }
+    /// ```
+    pub fn read_only(&self) -> MvdbReader<T> {
+        MvdbReader { db: self.clone() }
+    }
+
+    /// Open an existing file for reading only. If the file does not exist,
+    /// or the contained data does not match the schema of `T`, this will
+    /// return an Error. The file will never be created or written to
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use std::path::Path;
+    /// # use mvdb::{Mvdb, MvdbReader};
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct DemoData { foo: String, bar: Vec<u8>, baz: String }
+    /// # fn main() {
+    /// let file = Path::new("demo.json");
+    /// let reader: MvdbReader<DemoData> = Mvdb::open_read_only(&file)
+    ///     .expect("File does not exist, or schema mismatch");
+    /// # }
+    /// ```
+    pub fn open_read_only(path: &Path) -> Result<MvdbReader<T>> {
+        Self::open_read_only_with_storage(FileStorage::new(path))
+    }
+
+    /// Open the existing contents of a `Storage` for reading only. If the
+    /// storage is empty, or the contained data does not match the schema of
+    /// `T`, this will return an Error. The storage will never be written to
+    pub fn open_read_only_with_storage<S>(storage: S) -> Result<MvdbReader<T>>
+    where
+        S: Storage + 'static,
+    {
+        let storage: Arc<dyn Storage> = Arc::new(storage);
+        if !storage.exists()? {
+            bail!("Refusing to create storage for a read-only handle");
+        }
+        Ok(MvdbReader {
+            db: Self::from_storage_inner(storage, false)?,
+        })
+    }
+}
+
+impl<T> MvdbReader<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Provide atomic read-only access to the database contents via a closure.
+    /// Contents are accessed in-memory only, and will not re-read from the
+    /// storage file, or cause any writes
+    pub fn access<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.db.access(action)
+    }
+}
+
+impl<T> MvdbReader<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    /// Obtain a copy of the current database contents
+    pub fn snapshot(&self) -> Result<T> {
+        self.db.access(|db| db.clone())
+    }
+}
@@ -0,0 +1,550 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io::{BufRead, BufReader, ErrorKind as IoErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+
+use errors::*;
+use mvdb::{Inner, Mvdb};
+use patch;
+use reader::MvdbReader;
+use storage::{FileStorage, MemoryStorage, Storage};
+
+/// How often background threads check whether they have been stopped
+const POLL: Duration = Duration::from_millis(100);
+
+/// How long a `Follower` waits between attempts to reconnect
+const RETRY: Duration = Duration::from_millis(500);
+
+/// Newly written contents, and the generation they were written as
+pub(crate) type Update = (u64, Arc<str>);
+
+impl<T> Inner<T> {
+    /// Send newly written contents to every connected follower, forgetting
+    /// any that have disconnected
+    pub(crate) fn publish(&mut self, contents: &str) {
+        if self.replicas.is_empty() {
+            return;
+        }
+        let update: Update = (self.generation, contents.into());
+        self.replicas.retain(|tx| tx.send(update.clone()).is_ok());
+    }
+}
+
+/// A listener which streams the contents of an `Mvdb` to each connected
+/// `Follower`, started by `Mvdb::publish`
+///
+/// Each follower is first sent a full snapshot of the contents, then a JSON
+/// Patch for each later write, one JSON object per line. The listener and
+/// its connections are closed when the `Publisher` is dropped
+pub struct Publisher {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Publisher {
+    /// The address the publisher is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Listen on the given address, and stream the database contents to any
+    /// `Follower` that connects. Use port `0` to pick any free port, and
+    /// `Publisher::local_addr` to find out which was picked
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{Follower, Mvdb};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # use std::time::Duration;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String, bar: Vec<u8> }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let publisher = my_data.publish("127.0.0.1:0")
+    ///     .expect("Failed to listen");
+    ///
+    /// let follower: Follower<DemoData> = Follower::connect(publisher.local_addr())
+    ///     .expect("Failed to connect");
+    ///
+    /// my_data.access_mut(|db| db.foo = "Replicated".into())
+    ///     .expect("Failed to access file");
+    ///
+    /// let generation = my_data.generation().unwrap();
+    /// assert!(follower.wait_for_generation(generation, Duration::from_secs(5)));
+    ///
+    /// let foo = follower.access(|db| db.foo.clone())
+    ///     .expect("Failed to access file");
+    /// assert_eq!(foo, "Replicated");
+    /// # }
+    /// ```
+    pub fn publish<A: ToSocketAddrs>(&self, addr: A) -> Result<Publisher> {
+        let listener = TcpListener::bind(addr).chain_err(|| "Failed to bind listener")?;
+        let addr = listener.local_addr().chain_err(|| "Failed to bind listener")?;
+        listener
+            .set_nonblocking(true)
+            .chain_err(|| "Failed to bind listener")?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let db = self.clone();
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(_e) = db.add_replica(stream, stopped.clone()) {
+                            trace_event!(error = %_e, "failed to add follower");
+                        }
+                    }
+                    Err(ref e) if e.kind() == IoErrorKind::WouldBlock => thread::sleep(POLL),
+                    Err(_e) => {
+                        trace_event!(error = %_e, "failed to accept follower");
+                        thread::sleep(POLL);
+                    }
+                }
+            }
+        });
+
+        Ok(Publisher {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Register a newly connected follower, and start a thread to send it a
+    /// snapshot of the current contents followed by each later write
+    fn add_replica(&self, stream: TcpStream, stop: Arc<AtomicBool>) -> Result<()> {
+        stream
+            .set_nonblocking(false)
+            .chain_err(|| "Failed to configure connection")?;
+
+        // Take the snapshot and subscribe while holding the lock, so that
+        // no write is missed between the two
+        let (tx, rx) = channel();
+        let (generation, doc) = {
+            let mut inner = self.lock()?;
+            let doc = serde_json::to_value(&inner.data).chain_err(|| "Failed to serialize")?;
+            inner.replicas.push(tx);
+            (inner.generation, doc)
+        };
+
+        thread::spawn(move || {
+            if let Err(_e) = send_updates(stream, rx, generation, doc, &stop) {
+                trace_event!(error = %_e, "follower disconnected");
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Send a snapshot to a follower, then a patch for each update received,
+/// until the follower disconnects or the publisher is stopped
+fn send_updates(
+    mut stream: TcpStream,
+    updates: Receiver<Update>,
+    generation: u64,
+    mut doc: Value,
+    stop: &AtomicBool,
+) -> Result<()> {
+    send(&mut stream, &json!({ "generation": generation, "snapshot": doc }))?;
+
+    while !stop.load(Ordering::SeqCst) {
+        match updates.recv_timeout(POLL) {
+            // Writes made after the publisher was dropped are not sent
+            Ok(_) if stop.load(Ordering::SeqCst) => break,
+            Ok((generation, contents)) => {
+                let next: Value =
+                    serde_json::from_str(&contents).chain_err(|| "Deserialize error")?;
+                let patch = patch::diff(&doc, &next);
+                send(&mut stream, &json!({ "generation": generation, "patch": patch }))?;
+                doc = next;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok(())
+}
+
+fn send(stream: &mut TcpStream, message: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(message).chain_err(|| "Failed to serialize")?;
+    line.push(b'\n');
+    stream.write_all(&line).chain_err(|| "Failed to send")
+}
+
+/// A read-only copy of the contents of an `Mvdb` on another process,
+/// kept up to date by a `Publisher`
+///
+/// The copy is held in memory, and optionally persisted to a storage of
+/// its own. If the connection to the publisher is lost, the follower keeps
+/// its last contents and reconnects in the background, catching up with a
+/// fresh snapshot once reconnected. The background thread is stopped when
+/// the `Follower` is dropped
+pub struct Follower<T> {
+    db: Mvdb<T>,
+    state: Arc<FollowState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// State shared between a `Follower` and its background thread
+struct FollowState {
+    stop: AtomicBool,
+    generation: Mutex<u64>,
+    changed: Condvar,
+}
+
+/// A connection to a publisher, along with the contents it has sent
+struct Connection {
+    reader: BufReader<TcpStream>,
+    doc: Value,
+    generation: u64,
+}
+
+impl Connection {
+    /// Connect to a publisher, and wait for its snapshot. Returns `None` if
+    /// the follower was stopped while waiting
+    fn open(addr: &SocketAddr, state: &FollowState) -> Result<Option<Connection>> {
+        let stream = TcpStream::connect(addr).chain_err(|| "Failed to connect")?;
+        stream
+            .set_read_timeout(Some(POLL))
+            .chain_err(|| "Failed to configure connection")?;
+
+        let mut reader = BufReader::new(stream);
+        let message = match read_message(&mut reader, state)? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        match (message.get("generation").and_then(Value::as_u64), message.get("snapshot")) {
+            (Some(generation), Some(doc)) => Ok(Some(Connection {
+                doc: doc.clone(),
+                reader,
+                generation,
+            })),
+            _ => bail!("Expected a snapshot from the publisher"),
+        }
+    }
+
+    /// Wait for the next update from the publisher. Returns `false` if the
+    /// follower was stopped while waiting
+    fn next(&mut self, state: &FollowState) -> Result<bool> {
+        let message = match read_message(&mut self.reader, state)? {
+            Some(message) => message,
+            None => return Ok(false),
+        };
+        let generation = match message.get("generation").and_then(Value::as_u64) {
+            Some(generation) => generation,
+            None => bail!("Update from publisher has no generation"),
+        };
+
+        if let Some(doc) = message.get("snapshot") {
+            self.doc = doc.clone();
+        } else if let Some(patch) = message.get("patch") {
+            if generation != self.generation + 1 {
+                bail!("Update from publisher is out of sequence");
+            }
+            patch::apply_patch(&mut self.doc, patch)?;
+        } else {
+            bail!("Update from publisher has no contents");
+        }
+        self.generation = generation;
+        Ok(true)
+    }
+}
+
+/// Read one line from the publisher. Returns `None` if the follower was
+/// stopped while waiting
+fn read_message(reader: &mut BufReader<TcpStream>, state: &FollowState) -> Result<Option<Value>> {
+    let mut line = Vec::new();
+    loop {
+        if state.stop.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        // Partial lines are kept in `line` when the read times out
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => bail!("Connection closed by publisher"),
+            Ok(_) if line.ends_with(b"\n") => break,
+            Ok(_) => {}
+            Err(ref e) if e.kind() == IoErrorKind::WouldBlock || e.kind() == IoErrorKind::TimedOut => {}
+            Err(e) => return Err(e).chain_err(|| "Failed to receive"),
+        }
+    }
+    serde_json::from_slice(&line)
+        .map(Some)
+        .chain_err(|| "Deserialize error")
+}
+
+impl<T> Follower<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Connect to a `Publisher`, keeping a copy of its contents in memory.
+    /// Returns once the initial snapshot has been received
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::connect_with_storage(addr, MemoryStorage::new())
+    }
+
+    /// Connect to a `Publisher`, keeping a copy of its contents in memory
+    /// and in the given file. Returns once the initial snapshot has been
+    /// received and written
+    pub fn connect_to_file<A: ToSocketAddrs>(addr: A, path: &Path) -> Result<Self> {
+        Self::connect_with_storage(addr, FileStorage::new(path))
+    }
+
+    /// Connect to a `Publisher`, keeping a copy of its contents in memory
+    /// and in the given `Storage`. Returns once the initial snapshot has been
+    /// received and written
+    ///
+    /// # Examples
+    ///
+    /// A follower keeps its contents while the publisher is away, and
+    /// catches up once it is back:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::{Follower, Mvdb};
+    /// # use mvdb::storage::MemoryStorage;
+    /// # use std::time::Duration;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct DemoData { foo: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(DemoData::default(), MemoryStorage::new()).unwrap();
+    /// let publisher = my_data.publish("127.0.0.1:0").expect("Failed to listen");
+    /// let addr = publisher.local_addr();
+    ///
+    /// let copy = MemoryStorage::new();
+    /// let follower: Follower<DemoData> = Follower::connect_with_storage(addr, copy.clone())
+    ///     .expect("Failed to connect");
+    ///
+    /// my_data.access_mut(|db| db.foo = "first".into()).unwrap();
+    /// assert!(follower.wait_for_generation(my_data.generation().unwrap(), Duration::from_secs(5)));
+    ///
+    /// // Writes made while the publisher is away are not seen
+    /// drop(publisher);
+    /// my_data.access_mut(|db| db.foo = "second".into()).unwrap();
+    /// my_data.access_mut(|db| db.foo = "third".into()).unwrap();
+    /// let generation = my_data.generation().unwrap();
+    /// assert!(!follower.wait_for_generation(generation, Duration::from_millis(200)));
+    /// assert_eq!(follower.access(|db| db.foo.clone()).unwrap(), "first");
+    ///
+    /// // Until the follower reconnects, and catches up with a new snapshot
+    /// let publisher = my_data.publish(addr).expect("Failed to listen");
+    /// assert!(follower.wait_for_generation(generation, Duration::from_secs(5)));
+    /// assert_eq!(follower.access(|db| db.foo.clone()).unwrap(), "third");
+    /// assert_eq!(copy.contents().unwrap(), br#"{"foo":"third"}"#.to_vec());
+    ///
+    /// // Later writes are streamed as before
+    /// my_data.access_mut(|db| db.foo = "fourth".into()).unwrap();
+    /// assert!(follower.wait_for_generation(generation + 1, Duration::from_secs(5)));
+    /// assert_eq!(follower.access(|db| db.foo.clone()).unwrap(), "fourth");
+    /// # drop(publisher);
+    /// # }
+    /// ```
+    pub fn connect_with_storage<A, S>(addr: A, storage: S) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+        S: Storage + 'static,
+    {
+        let addr = match addr.to_socket_addrs().chain_err(|| "Invalid address")?.next() {
+            Some(addr) => addr,
+            None => bail!("Invalid address"),
+        };
+
+        let state = Arc::new(FollowState {
+            stop: AtomicBool::new(false),
+            generation: Mutex::new(0),
+            changed: Condvar::new(),
+        });
+
+        let conn = match Connection::open(&addr, &state)? {
+            Some(conn) => conn,
+            None => bail!("Follower was stopped"),
+        };
+        let data = serde_json::from_value(conn.doc.clone()).chain_err(|| "Deserialize error")?;
+        let db = Mvdb::new_with_storage(data, storage)?;
+        state.applied(conn.generation);
+
+        let thread = {
+            let db = db.clone();
+            let state = state.clone();
+            thread::spawn(move || follow(&db, &state, &addr, conn))
+        };
+
+        Ok(Follower {
+            db,
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// Provide atomic read-only access to the replicated contents via a
+    /// closure
+    pub fn access<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.db.access(action)
+    }
+
+    /// Obtain a read-only handle to the replicated contents, which will
+    /// observe updates from the publisher
+    pub fn reader(&self) -> MvdbReader<T> {
+        self.db.read_only()
+    }
+
+    /// The generation of the publisher's contents that was last applied
+    pub fn generation(&self) -> u64 {
+        self.state.generation()
+    }
+
+    /// Wait until contents of at least the given generation have been
+    /// applied, for at most the given time. Returns whether they were.
+    /// Timeouts too long to represent wait as long as necessary
+    pub fn wait_for_generation(&self, generation: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut current = match self.state.generation.lock() {
+            Ok(current) => current,
+            Err(_) => return false,
+        };
+        while *current < generation {
+            current = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    match self.state.changed.wait_timeout(current, deadline - now) {
+                        Ok((current, _)) => current,
+                        Err(_) => return false,
+                    }
+                }
+                None => match self.state.changed.wait(current) {
+                    Ok(current) => current,
+                    Err(_) => return false,
+                },
+            };
+        }
+        true
+    }
+}
+
+impl<T> Drop for Follower<T> {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl FollowState {
+    fn generation(&self) -> u64 {
+        self.generation.lock().map(|g| *g).unwrap_or(0)
+    }
+
+    fn applied(&self, generation: u64) {
+        if let Ok(mut current) = self.generation.lock() {
+            *current = generation;
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// Apply updates from the publisher until stopped, reconnecting whenever
+/// the connection is lost
+fn follow<T>(db: &Mvdb<T>, state: &FollowState, addr: &SocketAddr, mut conn: Connection)
+where
+    T: Serialize + DeserializeOwned,
+{
+    loop {
+        match conn.next(state) {
+            Ok(true) => {
+                if let Err(_e) = apply(db, &conn.doc) {
+                    trace_event!(error = %_e, "failed to apply update from publisher");
+                } else {
+                    state.applied(conn.generation);
+                }
+                continue;
+            }
+            Ok(false) => return,
+            Err(_e) => {
+                trace_event!(error = %_e, "lost connection to publisher");
+            }
+        }
+
+        conn = loop {
+            match Connection::open(addr, state) {
+                Ok(Some(conn)) => break conn,
+                Ok(None) => return,
+                Err(_e) => {
+                    trace_event!(error = %_e, "failed to reconnect to publisher");
+                }
+            }
+            if state.stop.load(Ordering::SeqCst) {
+                return;
+            }
+            thread::sleep(RETRY);
+        };
+        if apply(db, &conn.doc).is_ok() {
+            state.applied(conn.generation);
+        }
+    }
+}
+
+/// Replace the contents of the follower's copy, persisting them if they changed
+fn apply<T>(db: &Mvdb<T>, doc: &Value) -> Result<()>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let data = serde_json::from_value(doc.clone()).chain_err(|| "Deserialize error")?;
+    db.access_mut(|t| *t = data)
+}
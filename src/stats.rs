@@ -0,0 +1,177 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Emit a `tracing` event when the `tracing` feature is enabled, otherwise do nothing
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::debug!($($arg)*);
+        }
+    };
+}
+
+/// Enter a `tracing` span for the rest of the current scope when the `tracing`
+/// feature is enabled, otherwise do nothing
+macro_rules! trace_span {
+    ($name:expr) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!($name).entered();
+    };
+}
+
+/// The upper bounds of the buckets used by each `Histogram`
+const BUCKET_BOUNDS_MICROS: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// A snapshot of the counters kept for an `Mvdb`, shared by all handles to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of read-only accesses
+    pub reads: u64,
+
+    /// The number of times modified contents were written to storage
+    pub writes: u64,
+
+    /// The number of writable accesses that did not modify the contents,
+    /// and so did not write to storage
+    pub skipped_writes: u64,
+
+    /// The total number of bytes written to storage
+    pub bytes_written: u64,
+
+    /// The number of times the lock was already held when an access began
+    pub lock_contended: u64,
+
+    /// Time spent waiting to acquire the lock
+    pub lock_wait: Histogram,
+
+    /// Time spent serializing the contents, to detect changes or to write them
+    pub serialize: Histogram,
+
+    /// Time spent writing to storage
+    pub write: Histogram,
+}
+
+/// A distribution of durations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// The number of durations recorded
+    pub count: u64,
+
+    /// The sum of all recorded durations
+    pub total: Duration,
+
+    /// The longest recorded duration
+    pub max: Duration,
+
+    /// The number of recorded durations that fell in each bucket
+    pub buckets: Vec<Bucket>,
+}
+
+/// A range of durations within a `Histogram`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    /// The largest duration counted in this bucket, or `None` for
+    /// the last bucket, which counts everything larger
+    pub le: Option<Duration>,
+
+    /// The number of recorded durations in this bucket, and not
+    /// in any smaller bucket
+    pub count: u64,
+}
+
+/// The live counters behind `Stats`
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    pub(crate) reads: AtomicU64,
+    pub(crate) writes: AtomicU64,
+    pub(crate) skipped_writes: AtomicU64,
+    pub(crate) bytes_written: AtomicU64,
+    pub(crate) lock_contended: AtomicU64,
+    pub(crate) lock_wait: HistogramCounters,
+    pub(crate) serialize: HistogramCounters,
+    pub(crate) write: HistogramCounters,
+}
+
+/// The live counters behind a `Histogram`
+#[derive(Default)]
+pub(crate) struct HistogramCounters {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+    buckets: [AtomicU64; 7],
+}
+
+impl StatsCounters {
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            skipped_writes: self.skipped_writes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            lock_contended: self.lock_contended.load(Ordering::Relaxed),
+            lock_wait: self.lock_wait.snapshot(),
+            serialize: self.serialize.snapshot(),
+            write: self.write.snapshot(),
+        }
+    }
+}
+
+impl HistogramCounters {
+    /// Record the time elapsed since `start`, returning it
+    pub(crate) fn record_since(&self, start: Instant) -> Duration {
+        let elapsed = start.elapsed();
+        let nanos = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+        let micros = nanos / 1_000;
+        let bucket = BUCKET_BOUNDS_MICROS.iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MICROS.len());
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        elapsed
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let bounds = BUCKET_BOUNDS_MICROS.iter()
+            .map(|&micros| Some(Duration::from_micros(micros)))
+            .chain(Some(None));
+
+        Histogram {
+            count: self.count.load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+            buckets: bounds
+                .zip(self.buckets.iter())
+                .map(|(le, count)| Bucket {
+                    le,
+                    count: count.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
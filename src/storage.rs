@@ -0,0 +1,310 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Storage backends used to persist the contents of an `Mvdb`
+//!
+//! By default, `Mvdb` stores its contents in a file on disk, via `FileStorage`. The `Storage`
+//! trait may be implemented to persist data anywhere else, such as a blob in another database,
+//! or a partition of embedded flash. `MemoryStorage` is provided for tests, or for data that
+//! does not need to outlive the process.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use errors::*;
+
+/// Information about the contents currently held by a `Storage`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageMetadata {
+    /// The size of the stored contents, in bytes
+    pub len: u64,
+
+    /// The last time the contents were modified, if known
+    pub modified: Option<SystemTime>,
+}
+
+/// A place that the serialized contents of an `Mvdb` may be loaded from and stored to
+///
+/// Implementations are expected to replace the entire contents on each write, and to
+/// do so atomically, so that a failed write never leaves partial contents behind.
+pub trait Storage: Send + Sync {
+    /// Read the entire stored contents
+    fn read_all(&self) -> Result<Vec<u8>>;
+
+    /// Atomically replace the entire stored contents
+    fn write_all(&self, contents: &[u8]) -> Result<()>;
+
+    /// Check whether any contents have been stored yet
+    fn exists(&self) -> Result<bool>;
+
+    /// Obtain information about the stored contents
+    fn metadata(&self) -> Result<StorageMetadata>;
+
+    /// Add to the end of the stored contents, creating them if necessary.
+    /// This is used for append-only records, such as the version history.
+    /// The default implementation reads the entire contents and writes
+    /// them back with the addition
+    fn append(&self, contents: &[u8]) -> Result<()> {
+        let mut all = match self.exists()? {
+            true => self.read_all()?,
+            false => Vec::new(),
+        };
+        all.extend_from_slice(contents);
+        self.write_all(&all)
+    }
+}
+
+/// Store contents in a file on disk
+///
+/// Writes are made to a temporary file next to the target file, which is
+/// then renamed over the target, so readers never observe a partial write.
+/// If the path is a symlink, the file it points to is replaced, and the
+/// symlink is kept. The permissions of the replaced file are kept, but its
+/// owner is not, so the file is owned by the writing process afterwards.
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate mvdb;
+/// # use mvdb::storage::{FileStorage, Storage};
+/// # use std::fs;
+/// # #[cfg(unix)]
+/// # fn main() {
+/// use std::os::unix::fs::{symlink, PermissionsExt};
+///
+/// let dir = std::env::temp_dir().join(format!("mvdb-storage-{}", std::process::id()));
+/// fs::create_dir_all(&dir).unwrap();
+/// fs::write(dir.join("target.json"), b"{}").unwrap();
+/// fs::set_permissions(dir.join("target.json"), fs::Permissions::from_mode(0o600)).unwrap();
+/// symlink(dir.join("target.json"), dir.join("link.json")).unwrap();
+///
+/// FileStorage::new(&dir.join("link.json")).write_all(b"[]").unwrap();
+///
+/// assert!(fs::symlink_metadata(dir.join("link.json")).unwrap().file_type().is_symlink());
+/// assert_eq!(fs::read(dir.join("target.json")).unwrap(), b"[]");
+/// let mode = fs::metadata(dir.join("target.json")).unwrap().permissions().mode();
+/// assert_eq!(mode & 0o777, 0o600);
+/// # fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    /// Create a new `FileStorage` backed by the given path. The file is
+    /// not opened or created until it is read or written
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// The path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The file that writes replace, which is the file a symlink points to,
+    /// if the path is one
+    fn target(&self) -> PathBuf {
+        fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone())
+    }
+
+    /// A path for a temporary file next to the target, unique to this
+    /// write, so that concurrent writers do not use the same one
+    fn temp_path(target: &Path) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let mut name = target
+            .file_name()
+            .map(|n| n.to_os_string())
+            .unwrap_or_default();
+        name.push(format!(
+            ".{}.{}.tmp",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        target.with_file_name(name)
+    }
+}
+
+/// Write contents to a new file, with the permissions of the file it is
+/// going to replace, if there is one
+fn write_new(path: &Path, replacing: &Path, contents: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .chain_err(|| format!("Failed to create file: {:?}", path))?;
+    if let Ok(metadata) = fs::metadata(replacing) {
+        file.set_permissions(metadata.permissions())
+            .chain_err(|| format!("Failed to set permissions: {:?}", path))?;
+    }
+    file.write_all(contents)
+        .chain_err(|| "Failed to write to file")?;
+    file.sync_all()
+        .chain_err(|| "Failed to sync file")
+}
+
+impl Storage for FileStorage {
+    fn read_all(&self) -> Result<Vec<u8>> {
+        let mut file = File::open(&self.path)
+            .chain_err(|| format!("Failed to open file: {:?}", &self.path))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .chain_err(|| format!("Failed to read file: {:?}", &self.path))?;
+        Ok(contents)
+    }
+
+    fn write_all(&self, contents: &[u8]) -> Result<()> {
+        let target = self.target();
+        let temp_path = Self::temp_path(&target);
+        let written = write_new(&temp_path, &target, contents).and_then(|_| {
+            fs::rename(&temp_path, &target)
+                .chain_err(|| format!("Failed to replace file: {:?}", &self.path))
+        });
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written
+    }
+
+    fn exists(&self) -> Result<bool> {
+        Ok(self.path.is_file())
+    }
+
+    fn append(&self, contents: &[u8]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .chain_err(|| format!("Failed to open file: {:?}", &self.path))?;
+        file.write_all(contents)
+            .chain_err(|| "Failed to write to file")?;
+        file.sync_data()
+            .chain_err(|| "Failed to sync file")
+    }
+
+    fn metadata(&self) -> Result<StorageMetadata> {
+        let meta = fs::metadata(&self.path)
+            .chain_err(|| format!("Failed to read metadata: {:?}", &self.path))?;
+        Ok(StorageMetadata {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+/// Store contents in memory only
+///
+/// Clones of a `MemoryStorage` share the same contents, so a clone may be
+/// kept around to inspect what an `Mvdb` has written, which is useful in tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    inner: Arc<Mutex<Option<MemoryContents>>>,
+}
+
+/// The contents of a `MemoryStorage`, and when they were last written
+#[derive(Debug)]
+struct MemoryContents {
+    data: Vec<u8>,
+    modified: SystemTime,
+}
+
+impl MemoryContents {
+    fn new(data: &[u8]) -> Self {
+        Self {
+            data: data.to_vec(),
+            modified: SystemTime::now(),
+        }
+    }
+}
+
+impl MemoryStorage {
+    /// Create a new, empty `MemoryStorage`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new `MemoryStorage` that already holds the given contents
+    pub fn with_contents(contents: &[u8]) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Some(MemoryContents::new(contents)))),
+        }
+    }
+
+    /// Obtain a copy of the current contents, if any have been written
+    pub fn contents(&self) -> Option<Vec<u8>> {
+        match self.inner.lock() {
+            Ok(inner) => inner.as_ref().map(|c| c.data.clone()),
+            Err(_) => None,
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read_all(&self) -> Result<Vec<u8>> {
+        match self.contents() {
+            Some(data) => Ok(data),
+            None => bail!("No contents in memory storage"),
+        }
+    }
+
+    fn write_all(&self, contents: &[u8]) -> Result<()> {
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                *inner = Some(MemoryContents::new(contents));
+                Ok(())
+            }
+            Err(_) => bail!("failed to lock"),
+        }
+    }
+
+    fn exists(&self) -> Result<bool> {
+        match self.inner.lock() {
+            Ok(inner) => Ok(inner.is_some()),
+            Err(_) => bail!("failed to lock"),
+        }
+    }
+
+    fn metadata(&self) -> Result<StorageMetadata> {
+        match self.inner.lock() {
+            Ok(inner) => match *inner {
+                Some(ref contents) => Ok(StorageMetadata {
+                    len: contents.data.len() as u64,
+                    modified: Some(contents.modified),
+                }),
+                None => bail!("No contents in memory storage"),
+            },
+            Err(_) => bail!("failed to lock"),
+        }
+    }
+}
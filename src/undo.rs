@@ -0,0 +1,213 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::VecDeque;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::{Change, Mvdb};
+
+/// A bounded record of previous contents, used to undo and redo modifications
+pub(crate) struct UndoHistory {
+    limit: usize,
+    undo: VecDeque<UndoEntry>,
+    redo: Vec<UndoEntry>,
+}
+
+/// Serialized contents from before or after a modification
+struct UndoEntry {
+    label: Option<String>,
+    contents: String,
+}
+
+impl UndoHistory {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// Record the contents from before a new modification. This discards
+    /// anything that could previously have been redone
+    pub(crate) fn record(&mut self, contents: String, label: Option<&str>) {
+        self.redo.clear();
+        self.push_undo(UndoEntry {
+            label: label.map(|l| l.to_string()),
+            contents,
+        });
+    }
+
+    fn push_undo(&mut self, entry: UndoEntry) {
+        self.undo.push_back(entry);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Start recording the contents before each persisted modification, so
+    /// that it can later be undone. At most `limit` modifications are kept,
+    /// with the oldest discarded first
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Document { title: String }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Document::default(), MemoryStorage::new()).unwrap();
+    /// my_data.enable_undo(100).expect("Failed to access file");
+    ///
+    /// my_data.access_mut_labeled("Rename", |doc| doc.title = "Draft".into())
+    ///     .expect("Failed to access file");
+    /// assert_eq!(my_data.undo_labels().unwrap(), vec![Some("Rename".to_string())]);
+    ///
+    /// my_data.undo().expect("Failed to access file");
+    /// assert_eq!(my_data.access(|doc| doc.title.clone()).unwrap(), "");
+    ///
+    /// my_data.redo().expect("Failed to access file");
+    /// assert_eq!(my_data.access(|doc| doc.title.clone()).unwrap(), "Draft");
+    /// # }
+    /// ```
+    pub fn enable_undo(&self, limit: usize) -> Result<()> {
+        let mut inner = self.lock()?;
+        match inner.undo {
+            Some(ref mut undo) => {
+                undo.limit = limit;
+                while undo.undo.len() > limit {
+                    undo.undo.pop_front();
+                }
+            }
+            None => inner.undo = Some(UndoHistory::new(limit)),
+        }
+        Ok(())
+    }
+
+    /// Provide atomic writable access to the database contents via a closure,
+    /// as with `access_mut`. If the contents are modified, the undo history
+    /// entry for the modification will carry the given label
+    pub fn access_mut_labeled<F, R>(&self, label: &str, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.access_mut_inner(Change {
+            label: Some(label),
+            ..Change::default()
+        }, action)
+    }
+
+    /// Restore the contents from before the most recent modification, and write
+    /// them to the file. Returns `false` if there was nothing to undo
+    pub fn undo(&self) -> Result<bool> {
+        let mut inner = self.lock()?;
+        let entry = match inner.undo.as_mut().and_then(|u| u.undo.pop_back()) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        let change = Change {
+            label: entry.label.as_deref(),
+            reason: Some("undo"),
+            ..Change::default()
+        };
+        match self.restore_locked(&mut inner, &entry.contents, change) {
+            Ok(contents) => {
+                if let Some(ref mut undo) = inner.undo {
+                    undo.redo.push(UndoEntry {
+                        label: entry.label,
+                        contents,
+                    });
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                if let Some(ref mut undo) = inner.undo {
+                    undo.undo.push_back(entry);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Reapply the most recently undone modification, and write the contents
+    /// to the file. Returns `false` if there was nothing to redo
+    pub fn redo(&self) -> Result<bool> {
+        let mut inner = self.lock()?;
+        let entry = match inner.undo.as_mut().and_then(|u| u.redo.pop()) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        let change = Change {
+            label: entry.label.as_deref(),
+            reason: Some("redo"),
+            ..Change::default()
+        };
+        match self.restore_locked(&mut inner, &entry.contents, change) {
+            Ok(contents) => {
+                if let Some(ref mut undo) = inner.undo {
+                    undo.push_undo(UndoEntry {
+                        label: entry.label,
+                        contents,
+                    });
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                if let Some(ref mut undo) = inner.undo {
+                    undo.redo.push(entry);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// The labels of the modifications that may be undone, most recent first
+    pub fn undo_labels(&self) -> Result<Vec<Option<String>>> {
+        let inner = self.lock()?;
+        Ok(match inner.undo {
+            Some(ref undo) => undo.undo.iter().rev().map(|e| e.label.clone()).collect(),
+            None => Vec::new(),
+        })
+    }
+
+    /// The labels of the modifications that may be redone, most recent first
+    pub fn redo_labels(&self) -> Result<Vec<Option<String>>> {
+        let inner = self.lock()?;
+        Ok(match inner.undo {
+            Some(ref undo) => undo.redo.iter().rev().map(|e| e.label.clone()).collect(),
+            None => Vec::new(),
+        })
+    }
+}
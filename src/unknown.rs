@@ -0,0 +1,160 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::{Map, Value};
+
+use errors::*;
+use helpers::{just_load_from, serialize};
+use mvdb::{Inner, Mvdb};
+
+/// Find the fields of `stored` which are not present in `known`, at every
+/// level of nested objects. Fields within arrays are not tracked
+fn unknown_fields(stored: &Value, known: &Value) -> Option<Value> {
+    let (stored, known) = match (stored, known) {
+        (Value::Object(stored), Value::Object(known)) => (stored, known),
+        _ => return None,
+    };
+
+    let mut unknown = Map::new();
+    for (key, value) in stored {
+        match known.get(key) {
+            None => {
+                unknown.insert(key.clone(), value.clone());
+            }
+            Some(known) => {
+                if let Some(nested) = unknown_fields(value, known) {
+                    unknown.insert(key.clone(), nested);
+                }
+            }
+        }
+    }
+
+    match unknown.is_empty() {
+        true => None,
+        false => Some(Value::Object(unknown)),
+    }
+}
+
+/// Add the fields found by `unknown_fields` back into a document, wherever
+/// they are missing
+fn inject_unknown(doc: &mut Value, unknown: &Value) {
+    if let (Value::Object(doc), Value::Object(unknown)) = (doc, unknown) {
+        for (key, value) in unknown {
+            match doc.get_mut(key) {
+                Some(existing) => inject_unknown(existing, value),
+                None => {
+                    doc.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    /// Add any preserved unknown fields to serialized contents that are about
+    /// to be written to storage
+    pub(crate) fn with_unknown(&self, contents: String, pretty: bool) -> Result<String> {
+        match self.unknown {
+            None => Ok(contents),
+            Some(ref unknown) => {
+                let mut doc: Value = serde_json::from_str(&contents)
+                    .chain_err(|| "Deserialize error")?;
+                inject_unknown(&mut doc, unknown);
+                serialize(&doc, pretty)
+            }
+        }
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Keep any fields found in storage that are not part of `T`, such as
+    /// those written by a newer version of a program, and include them in
+    /// every later write, rather than discarding them. This should be called
+    /// just after loading, before any modifications are made. Fields are
+    /// tracked within nested objects, but not within arrays
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Config { name: String }
+    ///
+    /// # fn main() {
+    /// // Written by a newer version, which added `retries`
+    /// let storage = MemoryStorage::with_contents(br#"{"name":"demo","retries":3}"#);
+    ///
+    /// let my_data: Mvdb<Config> = Mvdb::with_storage(storage.clone())
+    ///     .expect("Could not load storage");
+    /// my_data.preserve_unknown_fields().expect("Could not load storage");
+    ///
+    /// my_data.access_mut(|cfg| cfg.name = "renamed".into())
+    ///     .expect("Failed to access file");
+    ///
+    /// assert_eq!(storage.contents().unwrap(), br#"{"name":"renamed","retries":3}"#.to_vec());
+    /// # }
+    /// ```
+    ///
+    /// Preserved fields are never recorded as changed in the audit log:
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct Config { name: String }
+    /// # fn main() {
+    /// # let storage = MemoryStorage::with_contents(br#"{"name":"demo","retries":3}"#);
+    /// # let my_data: Mvdb<Config> = Mvdb::with_storage(storage).unwrap();
+    /// my_data.preserve_unknown_fields().expect("Could not load storage");
+    ///
+    /// let audit = MemoryStorage::new();
+    /// my_data.enable_audit_with_storage(audit.clone()).expect("Audit log has been tampered with");
+    ///
+    /// my_data.access_mut(|cfg| cfg.name = "first".into()).expect("Failed to access file");
+    /// my_data.access_mut(|cfg| cfg.name = "second".into()).expect("Failed to access file");
+    ///
+    /// let log = String::from_utf8(audit.contents().unwrap()).unwrap();
+    /// assert_eq!(log.lines().count(), 2);
+    /// assert!(!log.contains("retries"));
+    /// # }
+    /// ```
+    pub fn preserve_unknown_fields(&self) -> Result<()> {
+        let mut inner = self.lock()?;
+        let stored: Value = just_load_from(&*self.storage)?;
+        let known = serde_json::to_value(&inner.data)
+            .chain_err(|| "Failed to serialize")?;
+
+        inner.unknown = unknown_fields(&stored, &known);
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::result::Result as StdResult;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::{Inner, Mvdb};
+
+/// A check that the contents of an `Mvdb` must pass before they are persisted
+pub(crate) type Validator<T> = Box<dyn Fn(&T) -> StdResult<(), String> + Send + Sync>;
+
+impl<T> Inner<T> {
+    /// Run every registered validator against the current contents
+    pub(crate) fn validate(&self) -> Result<()> {
+        for validator in &self.validators {
+            if let Err(reason) = validator(&self.data) {
+                bail!(ErrorKind::ValidationFailed(reason));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Register a validator that the database contents must pass before any
+    /// modification is persisted. Validators return `Err` with a reason when
+    /// the contents are invalid.
+    ///
+    /// The current contents, such as those just loaded from a file, are checked
+    /// immediately. If they are invalid, the validator is not registered, and an
+    /// `ErrorKind::ValidationFailed` is returned. This is the same error returned
+    /// by `access_mut` when a modification is rejected.
+    ///
+    /// A rejected modification is rolled back by deserializing the contents as
+    /// they were last serialized, which resets any fields that are not
+    /// serialized, such as those marked `#[serde(skip)]`, to their default
+    /// values. Call `rollback_by_clone` to keep them instead.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct Config { port: u16 }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config { port: 80 }, MemoryStorage::new()).unwrap();
+    /// my_data.add_validator(|cfg: &Config| match cfg.port {
+    ///     0 => Err("port must not be zero".into()),
+    ///     _ => Ok(()),
+    /// }).expect("Contents are invalid");
+    ///
+    /// // Invalid changes are rolled back, and never written
+    /// assert!(my_data.access_mut(|cfg| cfg.port = 0).is_err());
+    /// assert_eq!(my_data.access(|cfg| cfg.port).unwrap(), 80);
+    /// # }
+    /// ```
+    pub fn add_validator<F>(&self, validator: F) -> Result<()>
+    where
+        F: Fn(&T) -> StdResult<(), String> + Send + Sync + 'static,
+    {
+        let mut inner = self.lock()?;
+        if let Err(reason) = validator(&inner.data) {
+            bail!(ErrorKind::ValidationFailed(reason));
+        }
+        inner.validators.push(Box::new(validator));
+        Ok(())
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    /// Roll back rejected or failed modifications by restoring a clone of the
+    /// contents taken at the start of each writable access, rather than by
+    /// deserializing the last serialized contents. This keeps the values of
+    /// fields that are not serialized, such as those marked `#[serde(skip)]`,
+    /// at the cost of cloning the contents on every writable access
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// #[derive(Serialize, Deserialize, Clone)]
+    /// struct Config {
+    ///     port: u16,
+    ///     #[serde(skip)]
+    ///     log: Vec<String>,
+    /// }
+    ///
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config { port: 8080, log: vec![] }, MemoryStorage::new()).unwrap();
+    /// my_data.rollback_by_clone().expect("Failed to access file");
+    /// my_data.add_validator(|cfg: &Config| match cfg.port < 1024 {
+    ///     true => Err("port must not be privileged".into()),
+    ///     false => Ok(()),
+    /// }).expect("Contents are invalid");
+    ///
+    /// my_data.access_mut(|cfg| cfg.log.push("started".into())).expect("Failed to access file");
+    /// assert!(my_data.access_mut(|cfg| cfg.port = 5).is_err());
+    ///
+    /// // The rejected change is rolled back, but the log is kept
+    /// assert_eq!(my_data.access(|cfg| cfg.log.len()).unwrap(), 1);
+    /// # }
+    /// ```
+    pub fn rollback_by_clone(&self) -> Result<()> {
+        self.lock()?.cloner = Some(T::clone);
+        Ok(())
+    }
+}
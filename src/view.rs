@@ -0,0 +1,164 @@
+// MIT License
+//
+// Copyright (c) 2017 Anthony James Munns
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use errors::*;
+use mvdb::Mvdb;
+
+/// A handle to one part of the contents of an `Mvdb`
+///
+/// An `MvdbView` shares the lock and storage of the `Mvdb` it was projected
+/// from, so accesses are atomic with respect to all other handles, and any
+/// modifications made through the view are persisted as usual. Components
+/// that only care about one part of the contents can be given a view, without
+/// needing to know the full structure of the database.
+pub struct MvdbView<U> {
+    projection: Arc<dyn Projection<U>>,
+}
+
+/// Implement `Clone` manually, otherwise Rust expects `U` to also impl `Clone`,
+/// which is not necessary
+impl<U> Clone for MvdbView<U> {
+    fn clone(&self) -> Self {
+        Self {
+            projection: self.projection.clone(),
+        }
+    }
+}
+
+/// Type-erased access to the projected part of an `Mvdb`, so that `MvdbView`
+/// does not need to name the type of the whole database
+trait Projection<U>: Send + Sync {
+    fn access_dyn(&self, action: &mut dyn FnMut(&U)) -> Result<()>;
+    fn access_mut_dyn(&self, action: &mut dyn FnMut(&mut U)) -> Result<()>;
+}
+
+/// A projection from the contents of an `Mvdb<T>` to one part of type `U`
+struct Lens<T, G, M> {
+    db: Mvdb<T>,
+    get: G,
+    get_mut: M,
+}
+
+impl<T, U, G, M> Projection<U> for Lens<T, G, M>
+where
+    T: Serialize + DeserializeOwned + Send,
+    G: Fn(&T) -> &U + Send + Sync,
+    M: Fn(&mut T) -> &mut U + Send + Sync,
+{
+    fn access_dyn(&self, action: &mut dyn FnMut(&U)) -> Result<()> {
+        let guard = self.db.lock()?;
+        action((self.get)(guard.visible()));
+        Ok(())
+    }
+
+    fn access_mut_dyn(&self, action: &mut dyn FnMut(&mut U)) -> Result<()> {
+        self.db.access_mut(|t| action((self.get_mut)(t)))
+    }
+}
+
+impl<T> Mvdb<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Create a view focused on one part of the database contents. The
+    /// first closure selects the part for reading, the second for writing,
+    /// and both must select the same part
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate mvdb;
+    /// # use mvdb::Mvdb;
+    /// # use mvdb::storage::MemoryStorage;
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Network { port: u16 }
+    /// # #[derive(Serialize, Deserialize, Default)]
+    /// # struct Config { name: String, network: Network }
+    /// # fn main() {
+    /// # let my_data = Mvdb::new_with_storage(Config::default(), MemoryStorage::new()).unwrap();
+    /// let network = my_data.project(|t| &t.network, |t| &mut t.network);
+    ///
+    /// network.access_mut(|net| net.port = 8080)
+    ///     .expect("Failed to access file");
+    ///
+    /// let port = my_data.access(|db| db.network.port)
+    ///     .expect("Failed to access file");
+    /// assert_eq!(port, 8080);
+    /// # }
+    /// ```
+    pub fn project<U, G, M>(&self, get: G, get_mut: M) -> MvdbView<U>
+    where
+        U: 'static,
+        G: Fn(&T) -> &U + Send + Sync + 'static,
+        M: Fn(&mut T) -> &mut U + Send + Sync + 'static,
+    {
+        MvdbView {
+            projection: Arc::new(Lens {
+                db: self.clone(),
+                get,
+                get_mut,
+            }),
+        }
+    }
+}
+
+impl<U> MvdbView<U> {
+    /// Provide atomic read-only access to the projected contents via a closure.
+    /// Contents are accessed in-memory only, and will not re-read from the
+    /// storage file, or cause any writes
+    pub fn access<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&U) -> R,
+    {
+        let mut action = Some(action);
+        let mut ret = None;
+        self.projection.access_dyn(&mut |u| {
+            if let Some(action) = action.take() {
+                ret = Some(action(u));
+            }
+        })?;
+        ret.ok_or_else(|| "View was not accessed".into())
+    }
+
+    /// Provide atomic writable access to the projected contents via a closure.
+    /// If the database contents have changed after the access, the database
+    /// will be written to the file.
+    pub fn access_mut<F, R>(&self, action: F) -> Result<R>
+    where
+        F: FnOnce(&mut U) -> R,
+    {
+        let mut action = Some(action);
+        let mut ret = None;
+        self.projection.access_mut_dyn(&mut |u| {
+            if let Some(action) = action.take() {
+                ret = Some(action(u));
+            }
+        })?;
+        ret.ok_or_else(|| "View was not accessed".into())
+    }
+}